@@ -0,0 +1,277 @@
+//! Turns a command line into the call nushell would have sent for it, for
+//! running commands outside of nushell.
+use nu_plugin::{EvaluatedCall, LabeledError};
+use nu_protocol::{PluginSignature, Span, Spanned, SyntaxShape, Value};
+
+/// Splits `line` into words, along with where each word sits in `line`.
+pub(crate) fn tokenize(line: &str) -> Result<Vec<Spanned<String>>, LabeledError> {
+    let mut tokens = vec![];
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        let mut end = start;
+        if c == '"' || c == '\'' || c == '`' {
+            chars.next();
+            let mut closed = false;
+            for (idx, next) in chars.by_ref() {
+                end = idx + next.len_utf8();
+                if next == c {
+                    closed = true;
+                    break;
+                }
+                word.push(next);
+            }
+            if !closed {
+                return Err(LabeledError {
+                    label: "Unclosed quote".into(),
+                    msg: "this quote is never closed".into(),
+                    span: Some(Span::new(start, line.len())),
+                });
+            }
+        } else {
+            while let Some(&(idx, next)) = chars.peek() {
+                if next.is_whitespace() {
+                    break;
+                }
+                word.push(next);
+                end = idx + next.len_utf8();
+                chars.next();
+            }
+        }
+        tokens.push(Spanned { item: word, span: Span::new(start, end) });
+    }
+    Ok(tokens)
+}
+
+fn parse_arg(token: &Spanned<String>, shape: &SyntaxShape) -> Result<Value, LabeledError> {
+    let span = token.span;
+    let text = token.item.as_str();
+    let invalid = |expected: &str| LabeledError {
+        label: format!("Expected {}", expected),
+        msg: format!("could not parse `{}` as {}", text, expected),
+        span: Some(span),
+    };
+    Ok(match shape {
+        SyntaxShape::Int => Value::int(text.parse().map_err(|_| invalid("an int"))?, span),
+        SyntaxShape::Number => match text.parse::<i64>() {
+            Ok(val) => Value::int(val, span),
+            Err(_) => Value::float(text.parse().map_err(|_| invalid("a number"))?, span),
+        },
+        SyntaxShape::Boolean => Value::boolean(text.parse().map_err(|_| invalid("a bool"))?, span),
+        SyntaxShape::Any => {
+            if let Ok(val) = text.parse::<i64>() {
+                Value::int(val, span)
+            } else if let Ok(val) = text.parse::<f64>() {
+                Value::float(val, span)
+            } else if let Ok(val) = text.parse::<bool>() {
+                Value::boolean(val, span)
+            } else {
+                Value::string(text, span)
+            }
+        }
+        _ => Value::string(text, span),
+    })
+}
+
+/// Builds the call nushell would send for the words in `tokens`, using
+/// `signatures` to find the command and to tell flags, switches and
+/// positionals apart. `whole` is the span of the entire command line.
+/// Returns the name of the command along with the call.
+pub(crate) fn parse(signatures: &[PluginSignature], tokens: Vec<Spanned<String>>, whole: Span) -> Result<(String, EvaluatedCall), LabeledError> {
+
+    // Command names can contain spaces, so find the longest one that the
+    // line starts with.
+    let (sig, name_len) = signatures
+        .iter()
+        .filter_map(|sig| {
+            let words = sig.sig.name.split_whitespace().collect::<Vec<_>>();
+            let matches = words.len() <= tokens.len()
+                && words.iter().zip(tokens.iter()).all(|(word, token)| *word == token.item);
+            matches.then_some((&sig.sig, words.len()))
+        })
+        .max_by_key(|(_, len)| *len)
+        .ok_or_else(|| LabeledError {
+            label: "Unknown command".into(),
+            msg: "no command of this plugin matches".into(),
+            span: Some(whole),
+        })?;
+    let head = Span::new(tokens[0].span.start, tokens[name_len - 1].span.end);
+
+    let mut positional = vec![];
+    let mut named = vec![];
+    let mut rest = tokens[name_len..].iter();
+    while let Some(token) = rest.next() {
+        let (flag, inline_value) = if let Some(long) = token.item.strip_prefix("--") {
+            let (long, value) = match long.split_once('=') {
+                Some((long, value)) => (long, Some(value)),
+                None => (long, None),
+            };
+            (sig.named.iter().find(|f| f.long == long), value)
+        } else if token.item.len() > 1 && token.item.starts_with('-') && token.item.parse::<f64>().is_err() {
+            let short = token.item[1..].chars().next();
+            (sig.named.iter().find(|f| f.short.is_some() && f.short == short), None)
+        } else {
+            let shape = sig
+                .required_positional
+                .iter()
+                .chain(sig.optional_positional.iter())
+                .nth(positional.len())
+                .or(sig.rest_positional.as_ref())
+                .map(|arg| &arg.shape)
+                .ok_or_else(|| LabeledError {
+                    label: "Extra positional argument".into(),
+                    msg: format!("`{}` takes no more positional arguments", sig.name),
+                    span: Some(token.span),
+                })?;
+            positional.push(parse_arg(token, shape)?);
+            continue;
+        };
+
+        let flag = flag.ok_or_else(|| LabeledError {
+            label: "Unknown flag".into(),
+            msg: format!("`{}` has no flag `{}`", sig.name, token.item),
+            span: Some(token.span),
+        })?;
+        let flag_name = Spanned { item: flag.long.clone(), span: token.span };
+        match flag.arg {
+            None => named.push((flag_name, None)),
+            Some(ref shape) => {
+                let value = match inline_value {
+                    Some(value) => Spanned {
+                        item: value.to_string(),
+                        span: Span::new(token.span.end - value.len(), token.span.end),
+                    },
+                    None => rest.next().cloned().ok_or_else(|| LabeledError {
+                        label: "Missing flag value".into(),
+                        msg: format!("`--{}` needs a value", flag.long),
+                        span: Some(token.span),
+                    })?,
+                };
+                named.push((flag_name, Some(parse_arg(&value, shape)?)));
+            }
+        }
+    }
+
+    if positional.len() < sig.required_positional.len() {
+        let missing = &sig.required_positional[positional.len()];
+        return Err(LabeledError {
+            label: "Missing required positional argument".into(),
+            msg: format!("`{}` needs a value for `{}`", sig.name, missing.name),
+            span: Some(head),
+        });
+    }
+
+    Ok((sig.name.clone(), EvaluatedCall { head, positional, named }))
+}
+
+/// Joins already split arguments, such as `std::env::args()`, into one line,
+/// giving each word the span it has in that line.
+pub(crate) fn from_args(args: &[String]) -> (String, Vec<Spanned<String>>) {
+    let mut line = String::new();
+    let mut tokens = vec![];
+    for arg in args {
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        let start = line.len();
+        line.push_str(arg);
+        tokens.push(Spanned { item: arg.clone(), span: Span::new(start, line.len()) });
+    }
+    (line, tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use nu_protocol::SyntaxShape;
+
+    use super::*;
+
+    fn signatures() -> Vec<PluginSignature> {
+        vec![
+            PluginSignature::build("mtg"),
+            PluginSignature::build("mtg search")
+                .required("query", SyntaxShape::String, "the query")
+                .optional("limit", SyntaxShape::Int, "how many")
+                .named("set", SyntaxShape::String, "the set", Some('s'))
+                .named("cmc", SyntaxShape::Number, "mana value", None)
+                .switch("exact", "exact names", Some('e')),
+            PluginSignature::build("mtg tag").rest("tags", SyntaxShape::String, "tags"),
+        ]
+    }
+
+    fn parse_line(line: &str) -> Result<(String, EvaluatedCall), LabeledError> {
+        parse(&signatures(), tokenize(line)?, Span::new(0, line.len()))
+    }
+
+    #[test]
+    fn tokens_keep_their_spans_and_drop_quotes() {
+        let tokens = tokenize(r#"mtg  search "Lightning Bolt" 'x'"#).unwrap();
+        let words = tokens.iter().map(|t| (t.item.as_str(), t.span.start, t.span.end)).collect::<Vec<_>>();
+        assert_eq!(words, vec![("mtg", 0, 3), ("search", 5, 11), ("Lightning Bolt", 12, 28), ("x", 29, 32)]);
+    }
+
+    #[test]
+    fn unclosed_quotes_point_at_the_quote() {
+        let err = tokenize(r#"mtg search "Bolt"#).unwrap_err();
+        assert_eq!(err.label, "Unclosed quote");
+        assert_eq!(err.span, Some(Span::new(11, 16)));
+    }
+
+    #[test]
+    fn the_longest_command_name_wins() {
+        let (name, call) = parse_line("mtg search Opt 3").unwrap();
+        assert_eq!(name, "mtg search");
+        assert_eq!(call.head, Span::new(0, 10));
+        assert_eq!(call.positional, vec![Value::string("Opt", Span::new(11, 14)), Value::int(3, Span::new(15, 16))]);
+    }
+
+    #[test]
+    fn flags_take_values_inline_or_from_the_next_word() {
+        let (_, call) = parse_line("mtg search Opt --set=m21 --cmc 1.5 -e").unwrap();
+        assert_eq!(call.get_flag_value("set"), Some(Value::string("m21", Span::new(21, 24))));
+        assert_eq!(call.get_flag_value("cmc"), Some(Value::float(1.5, Span::new(31, 34))));
+        assert!(call.has_flag("exact"));
+
+        let (_, call) = parse_line("mtg search Opt -s m21").unwrap();
+        assert_eq!(call.get_flag_value("set"), Some(Value::string("m21", Span::new(18, 21))));
+    }
+
+    #[test]
+    fn negative_numbers_are_not_flags() {
+        let (_, call) = parse_line("mtg search Opt -3").unwrap();
+        assert_eq!(call.positional[1], Value::int(-3, Span::new(15, 17)));
+    }
+
+    #[test]
+    fn rest_positionals_take_every_remaining_word() {
+        let (_, call) = parse_line("mtg tag a b c").unwrap();
+        assert_eq!(call.positional.len(), 3);
+    }
+
+    #[test]
+    fn mistakes_are_reported_at_the_offending_word() {
+        let cases = [
+            ("lotus", "Unknown command", Span::new(0, 5)),
+            ("mtg search", "Missing required positional argument", Span::new(0, 10)),
+            ("mtg search Opt 3 4", "Extra positional argument", Span::new(17, 18)),
+            ("mtg search Opt --foil", "Unknown flag", Span::new(15, 21)),
+            ("mtg search Opt --set", "Missing flag value", Span::new(15, 20)),
+            ("mtg search Opt three", "Expected an int", Span::new(15, 20)),
+        ];
+        for (line, label, span) in cases {
+            let err = parse_line(line).unwrap_err();
+            assert_eq!((err.label.as_str(), err.span), (label, Some(span)), "for `{}`", line);
+        }
+    }
+
+    #[test]
+    fn split_arguments_are_joined_with_their_spans() {
+        let (line, tokens) = from_args(&["mtg".into(), "search".into(), "Lightning Bolt".into()]);
+        assert_eq!(line, "mtg search Lightning Bolt");
+        assert_eq!(tokens[2].span, Span::new(11, 25));
+    }
+}
@@ -0,0 +1,311 @@
+//! An on-disk cache of command results.
+//!
+//! Commands marked `#[cache("1h")]` keep their results for that long, in
+//! files named after a hash of the command name, its arguments and its
+//! input. Running the same call again within the hour answers from the
+//! file without running the handler. Such commands also get two switches:
+//! `--no-cache` skips the cache entirely, and `--refresh` runs the command
+//! and replaces whatever was cached. [`remember`] caches anything else
+//! worth keeping, such as the candidates a completer downloads.
+//!
+//! Files live in the plugin's directory under the user's cache directory
+//! (`~/.cache/nu_plugin_mtg` on Linux) unless [`configure`] picks another
+//! one, and the oldest are deleted once they add up to more than
+//! [`CacheConfig::max_size`]. A cache that can't be read or written is
+//! logged and otherwise ignored: it never fails a command.
+use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use nu_plugin::{EvaluatedCall, LabeledError};
+use nu_protocol::{Span, Value};
+use once_cell::sync::OnceCell;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+
+use crate::{from_value, Error, IntoValue, PipelineOutput};
+
+/// The switch that skips the cache for one call.
+pub const NO_CACHE_FLAG: &str = "no-cache";
+/// The switch that runs the command and caches the new result.
+pub const REFRESH_FLAG: &str = "refresh";
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Where to keep the files. `None` uses the plugin's directory under the
+    /// user's cache directory.
+    pub dir: Option<PathBuf>,
+    /// How many bytes of results to keep before deleting the oldest.
+    pub max_size: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            dir: None,
+            max_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+static CONFIG: OnceCell<CacheConfig> = OnceCell::new();
+
+/// Sets where and how much the cache keeps. Returns the config back if the
+/// cache was already configured or used.
+pub fn configure(config: CacheConfig) -> Result<(), CacheConfig> {
+    CONFIG.set(config)
+}
+
+fn config() -> &'static CacheConfig {
+    CONFIG.get_or_init(CacheConfig::default)
+}
+
+thread_local! {
+    static DISABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` with the cache turned off on this thread, so that
+/// [`testing`](crate::testing) neither reads nor writes the user's real
+/// cache.
+pub(crate) fn disabled<T>(f: impl FnOnce() -> T) -> T {
+    let was = DISABLED.with(|disabled| disabled.replace(true));
+    let output = f();
+    DISABLED.with(|disabled| disabled.set(was));
+    output
+}
+
+/// A directory of cached values, one file per key.
+pub struct Cache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Cache {
+            dir: dir.into(),
+            max_size: CacheConfig::default().max_size,
+        }
+    }
+
+    /// The cache every `#[cache]` command uses, as set up by [`configure`].
+    pub fn shared() -> Option<Self> {
+        let config = config();
+        let dir = match config.dir {
+            Some(ref dir) => dir.clone(),
+            None => {
+                let binary = std::env::current_exe().ok()?.file_stem()?.to_string_lossy().into_owned();
+                dirs::cache_dir()?.join(binary)
+            }
+        };
+        Some(Cache::new(dir).max_size(config.max_size))
+    }
+
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// The key for running `command` with `call`'s arguments on `input`.
+    /// Spans are left out, so the same call typed anywhere gets the same
+    /// key, and so are `--no-cache` and `--refresh`. `None` if an argument
+    /// can't be written down, such as a closure.
+    pub fn key(command: &str, call: &EvaluatedCall, input: &Value) -> Option<String> {
+        let named = call
+            .named
+            .iter()
+            .filter(|(name, _)| name.item != NO_CACHE_FLAG && name.item != REFRESH_FLAG)
+            .map(|(name, value)| {
+                let value = match value {
+                    Some(value) => from_value::<serde_json::Value>(value).ok()?,
+                    None => serde_json::Value::Bool(true),
+                };
+                Some((name.item.clone(), value))
+            })
+            .collect::<Option<serde_json::Map<_, _>>>()?;
+        let positional = call
+            .positional
+            .iter()
+            .map(|value| from_value::<serde_json::Value>(value).ok())
+            .collect::<Option<Vec<_>>>()?;
+        let input = from_value::<serde_json::Value>(input).ok()?;
+        let description = serde_json::json!({
+            "command": command,
+            "positional": positional,
+            "named": named,
+            "input": input,
+        });
+
+        Some(digest(&description))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.msgpack", key))
+    }
+
+    /// The value stored under `key` if it is younger than `ttl`, with every
+    /// span replaced by `span`.
+    pub fn get(&self, key: &str, ttl: Duration, span: Span) -> Result<Option<Value>, Error> {
+        let path = self.path(key);
+        let modified = match fs::metadata(&path).and_then(|meta| meta.modified()) {
+            Ok(modified) => modified,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+        if age > ttl {
+            return Ok(None);
+        }
+        let bytes = fs::read(&path)?;
+        let value = rmp_serde::from_slice::<Value>(&bytes)
+            .map_err(|e| Error::new(format!("Could not read {}", path.display())).source(e))?;
+        Ok(Some(respan(value, span)))
+    }
+
+    /// Stores `value` under `key`, then deletes the oldest files until the
+    /// cache fits in its size limit again.
+    pub fn put(&self, key: &str, value: &Value) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+        let bytes = rmp_serde::to_vec(value)
+            .map_err(|e| Error::new("Could not encode the value to cache").source(e))?;
+        // Write next to the final file and rename, so a reader never sees
+        // half a value.
+        let path = self.path(key);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, bytes)?;
+        fs::rename(&partial, &path)?;
+        self.evict()
+    }
+
+    /// Deletes every cached value.
+    pub fn clear(&self) -> Result<(), Error> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn evict(&self) -> Result<(), Error> {
+        let mut files = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if meta.is_file() {
+                files.push((meta.modified()?, meta.len(), entry.path()));
+            }
+        }
+        let mut total = files.iter().map(|(_, len, _)| len).sum::<u64>();
+        files.sort();
+        for (_, len, path) in files {
+            if total <= self.max_size {
+                break;
+            }
+            fs::remove_file(path)?;
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+fn digest(description: &serde_json::Value) -> String {
+    let digest = Sha256::digest(description.to_string().as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Points every span in `value` at `span`. Cached values still carry the
+/// spans of the call that produced them, which mean nothing in a new one.
+fn respan(value: Value, span: Span) -> Value {
+    match value {
+        Value::Record { cols, vals, .. } => Value::Record {
+            cols,
+            vals: vals.into_iter().map(|val| respan(val, span)).collect(),
+            span,
+        },
+        Value::List { vals, .. } => Value::List {
+            vals: vals.into_iter().map(|val| respan(val, span)).collect(),
+            span,
+        },
+        Value::Bool { val, .. } => Value::Bool { val, span },
+        Value::Int { val, .. } => Value::Int { val, span },
+        Value::Float { val, .. } => Value::Float { val, span },
+        Value::Filesize { val, .. } => Value::Filesize { val, span },
+        Value::Duration { val, .. } => Value::Duration { val, span },
+        Value::Date { val, .. } => Value::Date { val, span },
+        Value::String { val, .. } => Value::String { val, span },
+        Value::Binary { val, .. } => Value::Binary { val, span },
+        Value::Nothing { .. } => Value::Nothing { span },
+        other => other,
+    }
+}
+
+/// Runs `command` through the shared cache, honouring `--no-cache` and
+/// `--refresh`. Only successful results are kept.
+pub(crate) fn run_cached(
+    name: &str,
+    ttl: Duration,
+    call: &EvaluatedCall,
+    input: &Value,
+    command: impl FnOnce() -> Result<PipelineOutput, LabeledError>,
+) -> Result<PipelineOutput, LabeledError> {
+    if DISABLED.with(Cell::get) {
+        return command();
+    }
+    let cache = match Cache::shared() {
+        Some(cache) if !call.has_flag(NO_CACHE_FLAG) => cache,
+        _ => return command(),
+    };
+    let Some(key) = Cache::key(name, call, input) else {
+        return command();
+    };
+
+    if !call.has_flag(REFRESH_FLAG) {
+        match cache.get(&key, ttl, call.head) {
+            Ok(Some(value)) => {
+                log::debug!("answering from the cache");
+                return Ok(value.into());
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("could not read the cache: {}", e),
+        }
+    }
+
+    let value = command()?.into_value();
+    if let Err(e) = cache.put(&key, &value) {
+        log::warn!("could not write the cache: {}", e);
+    }
+    Ok(value.into())
+}
+
+/// Keeps what `compute` returns in the shared cache for `ttl`, under
+/// `name`. For results that aren't a command's output, such as the card
+/// names a completer offers, which would otherwise be fetched again on
+/// every tab press. Failures aren't kept.
+pub fn remember<T: IntoValue + DeserializeOwned>(
+    name: &str,
+    ttl: Duration,
+    span: Span,
+    compute: impl FnOnce() -> Result<T, LabeledError>,
+) -> Result<T, LabeledError> {
+    let cache = match Cache::shared() {
+        Some(cache) if !DISABLED.with(Cell::get) => cache,
+        _ => return compute(),
+    };
+    let key = digest(&serde_json::json!({ "remember": name }));
+
+    match cache.get(&key, ttl, span).map(|value| value.map(|value| from_value::<T>(&value))) {
+        Ok(Some(Ok(value))) => {
+            log::debug!("remembered `{}` from the cache", name);
+            return Ok(value);
+        }
+        Ok(Some(Err(e))) | Err(e) => log::warn!("could not read the cache: {}", e),
+        Ok(None) => {}
+    }
+
+    let value = compute()?;
+    if let Err(e) = cache.put(&key, &value.to_value(span)) {
+        log::warn!("could not write the cache: {}", e);
+    }
+    Ok(value)
+}
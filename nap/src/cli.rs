@@ -0,0 +1,267 @@
+//! Running plugin commands straight from a shell, without nushell.
+//!
+//! When a plugin binary is started with a command line, such as
+//! `nu_plugin_mtg mtg tutor Opt --fuzzy`, it runs that one command and
+//! prints the result instead of speaking the plugin protocol. The output is
+//! a plain table by default; `--output json` or `--output nuon`, given
+//! before the command name, picks another format, and `--config <file>`
+//! picks the configuration file, see [`config`](crate::config).
+use nu_plugin::LabeledError;
+use nu_protocol::{PluginSignature, Span, Value};
+
+use crate::{args, docs, from_value, panic, Commands};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Table,
+    Json,
+    Nuon,
+}
+
+/// Whether the arguments the plugin was started with ask for CLI mode.
+/// Nushell starts plugins without arguments (or with `--stdio`, in newer
+/// versions), and `-h`/`--help` on its own prints the plugin's help.
+pub(crate) fn requested(args: &[String]) -> bool {
+    match args.first().map(String::as_str) {
+        None | Some("--stdio") | Some("-h") | Some("--help") => false,
+        Some(_) => true,
+    }
+}
+
+/// Runs the command in `args`, prints its result, and returns the exit code
+/// for the process.
+pub(crate) fn run(plugin: &mut impl Commands, mut args: &[String]) -> i32 {
+    let mut output = Output::Table;
+    loop {
+        match args.first().map(String::as_str) {
+            Some("--output") => {
+                output = match args.get(1).map(String::as_str) {
+                    Some("table") => Output::Table,
+                    Some("json") => Output::Json,
+                    Some("nuon") => Output::Nuon,
+                    _ => {
+                        eprintln!("error: --output must be one of table, json or nuon");
+                        return 2;
+                    }
+                };
+            }
+            // Read by `config::load` itself.
+            Some("--config") if args.len() > 1 => {}
+            Some("--config") => {
+                eprintln!("error: --config needs a file");
+                return 2;
+            }
+            _ => break,
+        }
+        args = &args[2..];
+    }
+
+    let signatures = plugin.signature();
+    let (line, tokens) = args::from_args(args);
+    if tokens.iter().any(|t| t.item == "-h" || t.item == "--help") {
+        let sig = signatures
+            .iter()
+            .filter(|sig| line.starts_with(&sig.sig.name))
+            .max_by_key(|sig| sig.sig.name.len());
+        if let Some(sig) = sig {
+            print_command_help(sig);
+            return 0;
+        }
+    }
+
+    let result = args::parse(&signatures, tokens, Span::new(0, line.len())).and_then(|(name, call)| {
+        let input = Value::nothing(Span::unknown());
+        let output = plugin.run(&name, &call, &input)?;
+        panic::catch(&name, call.head, || Ok(output.into_value()))
+    });
+    let value = match result {
+        Ok(value) => value,
+        Err(e) => {
+            report(&line, &e);
+            return 1;
+        }
+    };
+
+    let rendered = match output {
+        Output::Table => Ok(table(&value)),
+        Output::Nuon => Ok(nuon(&value)),
+        Output::Json => from_value::<serde_json::Value>(&value)
+            .and_then(|json| Ok(serde_json::to_string_pretty(&json)?)),
+    };
+    match rendered {
+        Ok(text) => {
+            println!("{}", text);
+            0
+        }
+        Err(e) => {
+            report(&line, &e.into());
+            1
+        }
+    }
+}
+
+/// Prints `error` to stderr, underlining the part of `line` it points at.
+/// Errors with no line to point into, such as those of the plugin setup,
+/// pass an empty `line` and get their details printed below the message.
+pub(crate) fn report(line: &str, error: &LabeledError) {
+    eprintln!("error: {}", error.label);
+    match error.span {
+        Some(span) if span.end <= line.len() && span.start < span.end => {
+            eprintln!("  {}", line);
+            eprintln!("  {}{} {}", " ".repeat(span.start), "^".repeat(span.end - span.start), error.msg);
+        }
+        _ if !error.msg.is_empty() && error.msg != error.label => {
+            for detail in error.msg.lines() {
+                eprintln!("  {}", detail);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn print_command_help(sig: &PluginSignature) {
+    let sig = &sig.sig;
+    println!("{}\n", sig.usage);
+    if !sig.extra_usage.is_empty() {
+        println!("{}\n", sig.extra_usage);
+    }
+    println!("Usage:\n  > {}\n", docs::usage_line(sig));
+
+    let positionals = sig.required_positional.iter().chain(sig.optional_positional.iter()).chain(sig.rest_positional.iter());
+    let mut positionals = positionals.peekable();
+    if positionals.peek().is_some() {
+        println!("Parameters:");
+        for arg in positionals {
+            println!("  {} <{}>: {}", arg.name, arg.shape, arg.desc);
+        }
+        println!();
+    }
+
+    println!("Flags:");
+    for flag in &sig.named {
+        let short = flag.short.map(|c| format!("-{}, ", c)).unwrap_or_default();
+        let arg = flag.arg.as_ref().map(|shape| format!(" <{}>", shape)).unwrap_or_default();
+        println!("  {}--{}{}: {}", short, flag.long, arg, flag.desc);
+    }
+}
+
+/// How a value reads inside a table cell, where nested data is summarized.
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Record { cols, .. } => format!("{{record {} fields}}", cols.len()),
+        Value::List { vals, .. } => format!("[list {} items]", vals.len()),
+        other => scalar(other),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Bool { val, .. } => val.to_string(),
+        Value::Int { val, .. } => val.to_string(),
+        Value::Float { val, .. } => val.to_string(),
+        Value::Filesize { val, .. } => format!("{} B", val),
+        Value::Duration { val, .. } => format!("{}ns", val),
+        Value::Date { val, .. } => val.to_rfc3339(),
+        Value::String { val, .. } => val.clone(),
+        Value::Nothing { .. } => String::new(),
+        Value::Binary { val, .. } => format!("<binary {} bytes>", val.len()),
+        Value::CustomValue { val, .. } => val.value_string(),
+        Value::Error { error } => format!("error: {:?}", error),
+        other => format!("{:?}", other),
+    }
+}
+
+fn pad_rows(rows: &[Vec<String>]) -> String {
+    let widths = rows.iter().fold(vec![], |mut widths: Vec<usize>, row| {
+        for (idx, cell) in row.iter().enumerate() {
+            let width = cell.chars().count();
+            match widths.get_mut(idx) {
+                Some(w) => *w = (*w).max(width),
+                None => widths.push(width),
+            }
+        }
+        widths
+    });
+    rows.iter()
+        .map(|row| {
+            let cells = row.iter().enumerate().map(|(idx, cell)| format!("{:width$}", cell, width = widths[idx]));
+            cells.collect::<Vec<_>>().join("  ").trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renders `value` as plain text: records as one `column  value` line per
+/// column, lists of records as a table with a header, and anything else on
+/// its own.
+fn table(value: &Value) -> String {
+    match value {
+        Value::Record { cols, vals, .. } => {
+            let rows = cols.iter().zip(vals).map(|(col, val)| vec![col.clone(), cell(val)]).collect::<Vec<_>>();
+            pad_rows(&rows)
+        }
+        Value::List { vals, .. } => {
+            let mut columns: Vec<&String> = vec![];
+            for val in vals {
+                if let Value::Record { cols, .. } = val {
+                    for col in cols {
+                        if !columns.contains(&col) {
+                            columns.push(col);
+                        }
+                    }
+                }
+            }
+            let mut rows = vec![std::iter::once("#".to_string()).chain(columns.iter().map(|c| c.to_string())).collect::<Vec<_>>()];
+            for (idx, val) in vals.iter().enumerate() {
+                let mut row = vec![idx.to_string()];
+                match val {
+                    Value::Record { cols, vals, .. } => row.extend(columns.iter().map(|col| {
+                        cols.iter().position(|c| c == *col).map(|i| cell(&vals[i])).unwrap_or_default()
+                    })),
+                    other => row.push(cell(other)),
+                }
+                rows.push(row);
+            }
+            pad_rows(&rows)
+        }
+        other => scalar(other),
+    }
+}
+
+/// Quotes `text` as a nushell string literal. JSON string syntax is valid
+/// nushell.
+pub(crate) fn quote(text: &str) -> String {
+    serde_json::Value::String(text.to_string()).to_string()
+}
+
+/// Renders `value` as NUON, nushell's object notation.
+pub(crate) fn nuon(value: &Value) -> String {
+    match value {
+        Value::Bool { val, .. } => val.to_string(),
+        Value::Int { val, .. } => val.to_string(),
+        Value::Float { val, .. } if val.fract() == 0.0 && val.is_finite() => format!("{:.1}", val),
+        Value::Float { val, .. } => val.to_string(),
+        Value::Filesize { val, .. } => format!("{}b", val),
+        Value::Duration { val, .. } => format!("{}ns", val),
+        Value::Date { val, .. } => val.to_rfc3339(),
+        Value::String { val, .. } => quote(val),
+        Value::Nothing { .. } => "null".to_string(),
+        Value::Binary { val, .. } => {
+            format!("0x[{}]", val.iter().map(|b| format!("{:02X}", b)).collect::<String>())
+        }
+        Value::List { vals, .. } => format!("[{}]", vals.iter().map(nuon).collect::<Vec<_>>().join(", ")),
+        Value::Record { cols, vals, .. } => {
+            let fields = cols.iter().zip(vals).map(|(col, val)| {
+                let bare = !col.is_empty() && col.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+                let key = if bare { col.clone() } else { quote(col) };
+                format!("{}: {}", key, nuon(val))
+            });
+            format!("{{{}}}", fields.collect::<Vec<_>>().join(", "))
+        }
+        Value::CustomValue { val, span } => match val.to_base_value(*span) {
+            Ok(base) => nuon(&base),
+            Err(_) => quote(&val.value_string()),
+        },
+        other => quote(&scalar(other)),
+    }
+}
@@ -0,0 +1,440 @@
+//! Checking that a new release of a plugin doesn't break existing scripts.
+//!
+//! Plugin binaries print a [`SignatureDump`] when started with
+//! `--dump-signatures`. Keep the dump from the last release around, and
+//! [`diff`] it against the new one before releasing:
+//!
+//! ```ignore
+//! let changes = nap::compat::diff(&old, &new);
+//! if nap::compat::is_breaking(&changes) { /* bump the major version */ }
+//! ```
+use std::collections::BTreeMap;
+use std::fmt;
+
+use nu_protocol::{Flag, PluginSignature, PositionalArg};
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// Every command of a plugin, in a form that only changes when the commands
+/// do: commands are sorted by name and flags by their long name, and shapes
+/// and types are stored as they read in nushell's help.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignatureDump {
+    pub commands: Vec<CommandDump>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CommandDump {
+    pub name: String,
+    pub usage: String,
+    pub required: Vec<ArgDump>,
+    pub optional: Vec<ArgDump>,
+    pub rest: Option<ArgDump>,
+    pub flags: Vec<FlagDump>,
+    pub input_output: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArgDump {
+    pub name: String,
+    pub shape: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FlagDump {
+    pub long: String,
+    pub short: Option<char>,
+    /// The shape of the flag's value, or `None` for a switch.
+    pub shape: Option<String>,
+    pub required: bool,
+}
+
+impl From<&PositionalArg> for ArgDump {
+    fn from(arg: &PositionalArg) -> Self {
+        ArgDump { name: arg.name.clone(), shape: arg.shape.to_string() }
+    }
+}
+
+impl From<&Flag> for FlagDump {
+    fn from(flag: &Flag) -> Self {
+        FlagDump {
+            long: flag.long.clone(),
+            short: flag.short,
+            shape: flag.arg.as_ref().map(ToString::to_string),
+            required: flag.required,
+        }
+    }
+}
+
+impl SignatureDump {
+    pub fn new(signatures: &[PluginSignature]) -> Self {
+        let mut commands = signatures
+            .iter()
+            .map(|signature| {
+                let sig = &signature.sig;
+                let mut flags = sig.named.iter().map(FlagDump::from).collect::<Vec<_>>();
+                flags.sort_by(|a, b| a.long.cmp(&b.long));
+                CommandDump {
+                    name: sig.name.clone(),
+                    usage: sig.usage.clone(),
+                    required: sig.required_positional.iter().map(ArgDump::from).collect(),
+                    optional: sig.optional_positional.iter().map(ArgDump::from).collect(),
+                    rest: sig.rest_positional.as_ref().map(ArgDump::from),
+                    flags,
+                    input_output: sig
+                        .input_output_types
+                        .iter()
+                        .map(|(input, output)| (input.to_string(), output.to_string()))
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        commands.sort_by(|a, b| a.name.cmp(&b.name));
+        SignatureDump { commands }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("signature dumps always serialize")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        serde_json::from_str(json).map_err(|e| Error::from(e).label("Not a signature dump"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Scripts written against the old commands keep working.
+    NonBreaking,
+    /// Some script written against the old commands may now fail.
+    Breaking,
+}
+
+/// One difference between two dumps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub command: String,
+    pub severity: Severity,
+    pub description: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Breaking => "breaking",
+            Severity::NonBreaking => "non-breaking",
+        };
+        write!(f, "{}: `{}`: {}", severity, self.command, self.description)
+    }
+}
+
+/// Whether any of `changes` can break existing scripts.
+pub fn is_breaking(changes: &[Change]) -> bool {
+    changes.iter().any(|change| change.severity == Severity::Breaking)
+}
+
+struct Changes<'a> {
+    command: &'a str,
+    changes: Vec<Change>,
+}
+
+impl<'a> Changes<'a> {
+    fn push(&mut self, severity: Severity, description: String) {
+        self.changes.push(Change { command: self.command.to_string(), severity, description });
+    }
+}
+
+/// Lists what changed from `old` to `new`. Positional arguments are matched
+/// by position, since that's how scripts pass them, and flags by long name.
+pub fn diff(old: &SignatureDump, new: &SignatureDump) -> Vec<Change> {
+    let old_commands = old.commands.iter().map(|c| (c.name.as_str(), c)).collect::<BTreeMap<_, _>>();
+    let new_commands = new.commands.iter().map(|c| (c.name.as_str(), c)).collect::<BTreeMap<_, _>>();
+    let mut changes = vec![];
+    for (name, old) in &old_commands {
+        let mut command = Changes { command: name, changes: vec![] };
+        match new_commands.get(name) {
+            Some(new) => diff_command(&mut command, old, new),
+            None => command.push(Severity::Breaking, "command was removed".to_string()),
+        }
+        changes.extend(command.changes);
+    }
+    for name in new_commands.keys().filter(|name| !old_commands.contains_key(*name)) {
+        let mut command = Changes { command: name, changes: vec![] };
+        command.push(Severity::NonBreaking, "command was added".to_string());
+        changes.extend(command.changes);
+    }
+    changes
+}
+
+fn diff_command(changes: &mut Changes, old: &CommandDump, new: &CommandDump) {
+    use Severity::*;
+
+    if old.usage != new.usage {
+        changes.push(NonBreaking, "usage changed".to_string());
+    }
+
+    let positionals = |c: &CommandDump| {
+        let required = c.required.iter().map(|arg| (arg.clone(), true));
+        required.chain(c.optional.iter().map(|arg| (arg.clone(), false))).collect::<Vec<_>>()
+    };
+    let (old_args, new_args) = (positionals(old), positionals(new));
+    for idx in 0..old_args.len().max(new_args.len()) {
+        match (old_args.get(idx), new_args.get(idx)) {
+            (Some((old_arg, old_req)), Some((new_arg, new_req))) => {
+                if old_arg.shape != new_arg.shape {
+                    changes.push(Breaking, format!(
+                        "positional `{}` changed shape from {} to {}", new_arg.name, old_arg.shape, new_arg.shape
+                    ));
+                }
+                match (old_req, new_req) {
+                    (false, true) => changes.push(Breaking, format!("positional `{}` became required", new_arg.name)),
+                    (true, false) => changes.push(NonBreaking, format!("positional `{}` became optional", new_arg.name)),
+                    _ => {}
+                }
+                if old_arg.name != new_arg.name {
+                    changes.push(NonBreaking, format!("positional `{}` was renamed to `{}`", old_arg.name, new_arg.name));
+                }
+            }
+            (None, Some((new_arg, true))) => {
+                changes.push(Breaking, format!("new required positional `{}`", new_arg.name))
+            }
+            (None, Some((new_arg, false))) => {
+                changes.push(NonBreaking, format!("new optional positional `{}`", new_arg.name))
+            }
+            (Some((old_arg, _)), None) => match new.rest {
+                Some(ref rest) if rest.shape == old_arg.shape => {
+                    changes.push(NonBreaking, format!("positional `{}` is now taken by `...{}`", old_arg.name, rest.name))
+                }
+                _ => changes.push(Breaking, format!("positional `{}` was removed", old_arg.name)),
+            },
+            (None, None) => unreachable!(),
+        }
+    }
+
+    match (&old.rest, &new.rest) {
+        (Some(old_rest), Some(new_rest)) if old_rest.shape != new_rest.shape => changes.push(Breaking, format!(
+            "rest positional `{}` changed shape from {} to {}", new_rest.name, old_rest.shape, new_rest.shape
+        )),
+        (Some(old_rest), None) => changes.push(Breaking, format!("rest positional `{}` was removed", old_rest.name)),
+        (None, Some(new_rest)) => changes.push(NonBreaking, format!("new rest positional `{}`", new_rest.name)),
+        _ => {}
+    }
+
+    for old_flag in &old.flags {
+        let Some(new_flag) = new.flags.iter().find(|f| f.long == old_flag.long) else {
+            changes.push(Breaking, format!("flag `--{}` was removed", old_flag.long));
+            continue;
+        };
+        match (&old_flag.shape, &new_flag.shape) {
+            (None, Some(shape)) => changes.push(Breaking, format!("switch `--{}` now takes a {}", new_flag.long, shape)),
+            (Some(_), None) => changes.push(Breaking, format!("flag `--{}` became a switch", new_flag.long)),
+            (Some(old_shape), Some(new_shape)) if old_shape != new_shape => changes.push(Breaking, format!(
+                "flag `--{}` changed shape from {} to {}", new_flag.long, old_shape, new_shape
+            )),
+            _ => {}
+        }
+        match (old_flag.short, new_flag.short) {
+            (Some(old_short), new_short) if new_short != Some(old_short) => {
+                changes.push(Breaking, format!("flag `--{}` lost its short name `-{}`", new_flag.long, old_short))
+            }
+            (None, Some(new_short)) => {
+                changes.push(NonBreaking, format!("flag `--{}` gained the short name `-{}`", new_flag.long, new_short))
+            }
+            _ => {}
+        }
+        match (old_flag.required, new_flag.required) {
+            (false, true) => changes.push(Breaking, format!("flag `--{}` became required", new_flag.long)),
+            (true, false) => changes.push(NonBreaking, format!("flag `--{}` became optional", new_flag.long)),
+            _ => {}
+        }
+    }
+    for new_flag in new.flags.iter().filter(|f| !old.flags.iter().any(|old| old.long == f.long)) {
+        if new_flag.required {
+            changes.push(Breaking, format!("new required flag `--{}`", new_flag.long));
+        } else {
+            changes.push(NonBreaking, format!("new flag `--{}`", new_flag.long));
+        }
+    }
+
+    for (input, output) in &old.input_output {
+        if !new.input_output.iter().any(|(i, o)| i == input && o == output) {
+            changes.push(Breaking, format!("no longer accepts {} input producing {}", input, output));
+        }
+    }
+    for (input, output) in &new.input_output {
+        if !old.input_output.iter().any(|(i, o)| i == input && o == output) {
+            changes.push(NonBreaking, format!("now accepts {} input producing {}", input, output));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arg(name: &str, shape: &str) -> ArgDump {
+        ArgDump { name: name.to_string(), shape: shape.to_string() }
+    }
+
+    fn flag(long: &str, short: Option<char>, shape: Option<&str>) -> FlagDump {
+        FlagDump { long: long.to_string(), short, shape: shape.map(str::to_string), required: false }
+    }
+
+    fn command(name: &str) -> CommandDump {
+        CommandDump {
+            name: name.to_string(),
+            usage: String::new(),
+            required: vec![],
+            optional: vec![],
+            rest: None,
+            flags: vec![],
+            input_output: vec![],
+        }
+    }
+
+    fn dump(commands: Vec<CommandDump>) -> SignatureDump {
+        SignatureDump { commands }
+    }
+
+    /// The only change between `old` and `new`.
+    fn only_change(old: CommandDump, new: CommandDump) -> Change {
+        let mut changes = diff(&dump(vec![old]), &dump(vec![new]));
+        assert_eq!(changes.len(), 1, "{:?}", changes);
+        changes.remove(0)
+    }
+
+    #[test]
+    fn unchanged_signatures_have_no_changes() {
+        let mut tutor = command("mtg tutor");
+        tutor.required.push(arg("card_name", "string"));
+        tutor.flags.push(flag("fuzzy", Some('f'), None));
+        assert!(diff(&dump(vec![tutor.clone()]), &dump(vec![tutor])).is_empty());
+    }
+
+    #[test]
+    fn removed_command_is_breaking_and_added_is_not() {
+        let changes = diff(&dump(vec![command("mtg tutor")]), &dump(vec![command("mtg search")]));
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].command, "mtg tutor");
+        assert_eq!(changes[0].severity, Severity::Breaking);
+        assert_eq!(changes[1].command, "mtg search");
+        assert_eq!(changes[1].severity, Severity::NonBreaking);
+        assert!(is_breaking(&changes));
+    }
+
+    #[test]
+    fn new_required_positional_is_breaking() {
+        let mut new = command("mtg tutor");
+        new.required.push(arg("card_name", "string"));
+        let change = only_change(command("mtg tutor"), new);
+        assert_eq!(change.severity, Severity::Breaking);
+        assert_eq!(change.description, "new required positional `card_name`");
+    }
+
+    #[test]
+    fn new_optional_positional_is_not_breaking() {
+        let mut new = command("mtg tutor");
+        new.optional.push(arg("card_name", "string"));
+        assert_eq!(only_change(command("mtg tutor"), new).severity, Severity::NonBreaking);
+    }
+
+    #[test]
+    fn optional_positional_becoming_required_is_breaking() {
+        let mut old = command("mtg search");
+        old.optional.push(arg("name", "string"));
+        let mut new = command("mtg search");
+        new.required.push(arg("name", "string"));
+        let change = only_change(old, new);
+        assert_eq!(change.severity, Severity::Breaking);
+        assert_eq!(change.description, "positional `name` became required");
+    }
+
+    #[test]
+    fn rest_positional_absorbing_a_removed_positional_is_not_breaking() {
+        let mut old = command("syn note get");
+        old.optional.push(arg("id", "string"));
+        let mut new = command("syn note get");
+        new.rest = Some(arg("ids", "string"));
+
+        let changes = diff(&dump(vec![old.clone()]), &dump(vec![new]));
+        assert!(!is_breaking(&changes), "{:?}", changes);
+        assert!(changes.iter().any(|c| c.description == "positional `id` is now taken by `...ids`"));
+
+        // Not if the rest positional takes a different shape.
+        let mut new = command("syn note get");
+        new.rest = Some(arg("ids", "int"));
+        let changes = diff(&dump(vec![old]), &dump(vec![new]));
+        assert!(changes.iter().any(|c| {
+            c.severity == Severity::Breaking && c.description == "positional `id` was removed"
+        }));
+    }
+
+    #[test]
+    fn flag_becoming_a_switch_is_breaking() {
+        let mut old = command("mtg search");
+        old.flags.push(flag("set", None, Some("string")));
+        let mut new = command("mtg search");
+        new.flags.push(flag("set", None, None));
+        let change = only_change(old, new);
+        assert_eq!(change.severity, Severity::Breaking);
+        assert_eq!(change.description, "flag `--set` became a switch");
+    }
+
+    #[test]
+    fn switch_taking_a_value_is_breaking() {
+        let mut old = command("mtg tutor");
+        old.flags.push(flag("fuzzy", None, None));
+        let mut new = command("mtg tutor");
+        new.flags.push(flag("fuzzy", None, Some("string")));
+        assert_eq!(only_change(old, new).severity, Severity::Breaking);
+    }
+
+    #[test]
+    fn losing_a_short_name_is_breaking_and_gaining_one_is_not() {
+        let mut with_short = command("mtg tutor");
+        with_short.flags.push(flag("fuzzy", Some('f'), None));
+        let mut without_short = command("mtg tutor");
+        without_short.flags.push(flag("fuzzy", None, None));
+
+        let lost = only_change(with_short.clone(), without_short.clone());
+        assert_eq!(lost.severity, Severity::Breaking);
+        assert_eq!(lost.description, "flag `--fuzzy` lost its short name `-f`");
+
+        assert_eq!(only_change(without_short, with_short).severity, Severity::NonBreaking);
+    }
+
+    #[test]
+    fn removed_flag_is_breaking_and_new_optional_flag_is_not() {
+        let mut old = command("mtg search");
+        old.flags.push(flag("set", None, Some("string")));
+        let mut new = command("mtg search");
+        new.flags.push(flag("cmc", None, Some("string")));
+        let changes = diff(&dump(vec![old]), &dump(vec![new]));
+        assert_eq!(changes.len(), 2, "{:?}", changes);
+        assert_eq!((changes[0].severity, changes[0].description.as_str()), (Severity::Breaking, "flag `--set` was removed"));
+        assert_eq!((changes[1].severity, changes[1].description.as_str()), (Severity::NonBreaking, "new flag `--cmc`"));
+    }
+
+    #[test]
+    fn new_required_flag_is_breaking() {
+        let mut new = command("mtg search");
+        new.flags.push(FlagDump { required: true, ..flag("set", None, Some("string")) });
+        assert_eq!(only_change(command("mtg search"), new).severity, Severity::Breaking);
+    }
+
+    #[test]
+    fn dropped_input_output_is_breaking() {
+        let mut old = command("syn note get");
+        old.input_output.push(("list<string>".to_string(), "list<any>".to_string()));
+        assert_eq!(only_change(old, command("syn note get")).severity, Severity::Breaking);
+    }
+
+    #[test]
+    fn dumps_round_trip_through_json() {
+        let mut tutor = command("mtg tutor");
+        tutor.flags.push(flag("fuzzy", Some('f'), None));
+        let dump = dump(vec![tutor]);
+        assert_eq!(SignatureDump::from_json(&dump.to_json()).unwrap(), dump);
+    }
+}
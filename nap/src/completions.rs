@@ -0,0 +1,148 @@
+//! Tab completion for plugin commands, through a generated nushell module.
+//!
+//! Plugin signatures can't point at completers, so instead a field marked
+//! `#[complete(fn_name)]` gets a hidden command, `nap complete <command>
+//! <argument>`, that lists its completions by calling `fn_name`. The module
+//! from [`render_module`] wraps every command in a `def` of the same name
+//! whose arguments complete through those hidden commands. After
+//! `use mtg.nu`, the wrappers shadow the plugin's own commands.
+//!
+//! Plugin binaries write the module when started with
+//! `--generate-module <dir>`.
+use std::fmt::Write;
+
+use nu_protocol::{PluginSignature, Signature};
+
+use crate::cli::quote;
+use crate::docs::Page;
+use crate::Error;
+
+const PREFIX: &str = "nap complete ";
+
+/// The name of the hidden command listing completions for `argument` of
+/// `command`.
+pub fn completer_name(command: &str, argument: &str) -> String {
+    format!("{}{} {}", PREFIX, command, argument)
+}
+
+/// Whether `sig` is one of the hidden completion commands, which don't get
+/// documented or wrapped.
+pub fn is_completer(sig: &PluginSignature) -> bool {
+    sig.sig.name.starts_with(PREFIX)
+}
+
+/// Flags and positionals become variables with dashes turned to underscores.
+fn variable(name: &str) -> String {
+    format!("${}", name.replace('-', "_"))
+}
+
+fn comment(desc: &str) -> String {
+    if desc.is_empty() { String::new() } else { format!("  # {}", desc) }
+}
+
+/// The type annotation for an argument, pointing at its completer if it
+/// has one.
+fn annotation(signatures: &[PluginSignature], sig: &Signature, argument: &str, shape: String) -> String {
+    let completer = completer_name(&sig.name, argument);
+    if signatures.iter().any(|s| s.sig.name == completer) {
+        format!("{}@{}", shape, quote(&format!("nu-complete {} {}", sig.name, argument)))
+    } else {
+        shape
+    }
+}
+
+/// Writes the call to the plugin command, with one branch per combination
+/// of switches, since a switch can't be passed on from a variable.
+fn forward(out: &mut String, indent: usize, call: &str, switches: &[&str]) {
+    match switches.split_first() {
+        None => {
+            let _ = writeln!(out, "{:indent$}{}", "", call, indent = indent);
+        }
+        Some((switch, rest)) => {
+            let _ = writeln!(out, "{:indent$}if {} {{", "", variable(switch), indent = indent);
+            forward(out, indent + 4, &format!("{} --{}", call, switch), rest);
+            let _ = writeln!(out, "{:indent$}}} else {{", "", indent = indent);
+            forward(out, indent + 4, call, rest);
+            let _ = writeln!(out, "{:indent$}}}", "", indent = indent);
+        }
+    }
+}
+
+fn wrapper(out: &mut String, signatures: &[PluginSignature], sig: &Signature, local_name: &str) {
+    if !sig.usage.is_empty() {
+        let _ = writeln!(out, "# {}", sig.usage);
+    }
+    let _ = writeln!(out, "export def {} [", quote(local_name));
+
+    let mut call = sig.name.clone();
+    let optional = sig.optional_positional.iter().map(|arg| (arg, "?"));
+    for (arg, suffix) in sig.required_positional.iter().map(|arg| (arg, "")).chain(optional) {
+        let shape = annotation(signatures, sig, &arg.name, arg.shape.to_string());
+        let _ = writeln!(out, "    {}{}: {}{}", arg.name, suffix, shape, comment(&arg.desc));
+        call.push_str(&format!(" {}", variable(&arg.name)));
+    }
+
+    let mut switches = vec![];
+    for flag in sig.named.iter().filter(|flag| flag.long != "help") {
+        let short = flag.short.map(|short| format!("(-{})", short)).unwrap_or_default();
+        match flag.arg {
+            Some(ref shape) => {
+                let shape = annotation(signatures, sig, &flag.long, shape.to_string());
+                let _ = writeln!(out, "    --{}{}: {}{}", flag.long, short, shape, comment(&flag.desc));
+                call.push_str(&format!(" --{} {}", flag.long, variable(&flag.long)));
+            }
+            None => {
+                let _ = writeln!(out, "    --{}{}{}", flag.long, short, comment(&flag.desc));
+                switches.push(flag.long.as_str());
+            }
+        }
+    }
+
+    let _ = writeln!(out, "] {{");
+    forward(out, 4, &call, &switches);
+    let _ = writeln!(out, "}}\n");
+}
+
+/// Renders the wrapper module for `signatures`. Every command has to start
+/// with the same word, such as `mtg`, which names the module: `mtg.nu`.
+pub fn render_module(signatures: &[PluginSignature]) -> Result<Page, Error> {
+    let commands = signatures.iter().filter(|sig| !is_completer(sig)).collect::<Vec<_>>();
+    let module = commands
+        .first()
+        .and_then(|sig| sig.sig.name.split_whitespace().next())
+        .ok_or_else(|| Error::new("The plugin has no commands to wrap"))?;
+
+    let mut out = format!(
+        "# Generated by nap from the `{module}` plugin's signatures. Load it with\n\
+         # `use {module}.nu` after registering the plugin.\n\n"
+    );
+
+    for sig in signatures.iter().filter(|sig| is_completer(sig)) {
+        let target = &sig.sig.name[PREFIX.len()..];
+        let _ = writeln!(
+            out,
+            "def {} [] {{ {} }}",
+            quote(&format!("nu-complete {}", target)),
+            sig.sig.name
+        );
+    }
+    out.push('\n');
+
+    for sig in commands {
+        let local_name = sig
+            .sig
+            .name
+            .strip_prefix(module)
+            .and_then(|rest| rest.strip_prefix(' '))
+            .ok_or_else(|| {
+                Error::new(format!("Can't wrap `{}` in the `{}` module", sig.sig.name, module))
+                    .help(format!("every command has to start with `{} `", module))
+            })?;
+        wrapper(&mut out, signatures, &sig.sig, local_name);
+    }
+
+    Ok(Page {
+        name: format!("{}.nu", module),
+        contents: out.trim_end().to_string() + "\n",
+    })
+}
@@ -0,0 +1,142 @@
+//! Configuration files for plugins.
+//!
+//! [`load`] reads `config.toml` from the plugin's directory under the user's
+//! config directory (`~/.config/nu_plugin_syn/config.toml` on Linux), then
+//! lets environment variables override single keys: with the plugin name
+//! upper-cased as prefix, `NU_PLUGIN_SYN_SERVER=nas.local` sets `server`,
+//! and `NU_PLUGIN_SYN_TLS__CA_FILE` sets `ca_file` in the `[tls]` table.
+//!
+//! A different file can be picked with `NU_PLUGIN_SYN_CONFIG`, or with
+//! `--config <file>` when running a command from the command line.
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use toml::{Table, Value};
+
+use crate::Error;
+
+/// The file given with `--config`, among the options `cli` accepts before
+/// the command name. Read straight from argv, since plugins may load their
+/// config before serving anything.
+fn path_from_args() -> Option<PathBuf> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut options = args.chunks(2);
+    let mut path = None;
+    while let Some([option, value]) = options.next() {
+        match option.as_str() {
+            "--config" => path = Some(PathBuf::from(value)),
+            "--output" => {}
+            _ => break,
+        }
+    }
+    path
+}
+
+fn env_prefix(plugin_name: &str) -> String {
+    let mut prefix = plugin_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect::<String>();
+    prefix.push('_');
+    prefix
+}
+
+/// Where [`load`] looks for the configuration of `plugin_name` by default.
+pub fn default_path(plugin_name: &str) -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join(plugin_name).join("config.toml"))
+}
+
+/// `line:column`, counting from 1, of byte `offset` in `text`.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |line| line.chars().count()) + 1;
+    (line, column)
+}
+
+fn invalid(path: &Path, contents: &str, error: &toml::de::Error) -> Error {
+    let location = match error.span() {
+        Some(span) => {
+            let (line, column) = line_column(contents, span.start);
+            format!("{}:{}:{}", path.display(), line, column)
+        }
+        None => path.display().to_string(),
+    };
+    Error::new("Invalid configuration").label(format!("{}: {}", location, error.message().trim()))
+}
+
+/// Reads an environment variable's value as a TOML value if it is one, such
+/// as `true` or `30`, and as a string otherwise.
+fn env_value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn overlay_env(table: &mut Table, prefix: &str) {
+    for (key, raw) in std::env::vars() {
+        let Some(path) = key.strip_prefix(prefix) else {
+            continue;
+        };
+        if path == "CONFIG" || path.is_empty() {
+            continue;
+        }
+        let mut keys = path.split("__").map(str::to_lowercase).collect::<Vec<_>>();
+        let last = keys.pop().expect("split always yields one item");
+        let mut target = &mut *table;
+        for key in keys {
+            let entry = target.entry(key).or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            target = entry.as_table_mut().expect("just made sure it's a table");
+        }
+        target.insert(last, env_value(&raw));
+    }
+}
+
+/// Loads the configuration of `plugin_name`, from its file and environment
+/// variables. A missing default file counts as empty, so `C` should fall
+/// back to defaults for anything that isn't set, with `#[serde(default)]`.
+pub fn load<C: DeserializeOwned>(plugin_name: &str) -> Result<C, Error> {
+    let prefix = env_prefix(plugin_name);
+    let explicit = path_from_args().or_else(|| std::env::var_os(format!("{}CONFIG", prefix)).map(PathBuf::from));
+    load_from(plugin_name, explicit.as_deref())
+}
+
+/// Like [`load`], but reading `path` instead of the default file if given.
+/// Unlike the default file, `path` has to exist.
+pub fn load_from<C: DeserializeOwned>(plugin_name: &str, path: Option<&Path>) -> Result<C, Error> {
+    let (path, required) = match path {
+        Some(path) => (Some(path.to_path_buf()), true),
+        None => (default_path(plugin_name), false),
+    };
+
+    let mut contents = String::new();
+    if let Some(ref path) = path {
+        match std::fs::read_to_string(path) {
+            Ok(text) => contents = text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {}
+            Err(e) => {
+                return Err(Error::new(format!("Could not read {}", path.display())).source(e));
+            }
+        }
+    }
+    let path = path.unwrap_or_else(|| PathBuf::from("config.toml"));
+
+    let mut table = toml::from_str::<Table>(&contents).map_err(|e| invalid(&path, &contents, &e))?;
+    overlay_env(&mut table, &env_prefix(plugin_name));
+
+    Value::Table(table).try_into::<C>().map_err(|e| {
+        // Errors from the merged table don't know where they came from.
+        // Reading the file alone again finds the line, if the problem is
+        // in the file rather than in the environment.
+        match toml::from_str::<C>(&contents) {
+            Err(file_error) if file_error.span().is_some() => invalid(&path, &contents, &file_error),
+            _ => Error::new("Invalid configuration")
+                .label(format!("{}: {}", path.display(), e.message().trim()))
+                .help(format!("check the {}* environment variables too", env_prefix(plugin_name))),
+        }
+    })
+}
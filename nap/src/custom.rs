@@ -0,0 +1,150 @@
+//! Opaque values that nushell holds on to without looking inside.
+//!
+//! Some results are better handed back as a handle than converted into a
+//! record up front: a session, or a full API object whose record form is
+//! expensive to build. Implement [`PluginValue`] for such a type and return
+//! it with [`into_value`]. Nushell keeps the bytes, shows the value by its
+//! type name, and asks the plugin to expand it with
+//! [`PluginValue::to_base_value`] only when it has to, such as when the
+//! value is printed or saved. The 0.77 protocol has no call for following
+//! a cell path into a custom value, so values are only ever expanded whole.
+//!
+//! Handing the value to another command of the same plugin, as in
+//! `mtg tutor Opt --handle | mtg prints`, gives that command the value back
+//! as it was; [`from_value`] turns the input back into the Rust type.
+//!
+//! Each call runs in a fresh plugin process, so a process expanding a value
+//! may never have created one. Register every [`PluginValue`] type with
+//! [`PluginServer::custom_value`](crate::PluginServer::custom_value) (or
+//! [`register`]) so that it can.
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Mutex;
+
+use nu_plugin::LabeledError;
+use nu_protocol::{CustomValue, ShellError, Span, Value};
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+pub trait PluginValue: Serialize + DeserializeOwned + Debug + Send + Sync + 'static {
+    /// What nushell shows as the value's type. Also identifies the type when
+    /// the value comes back, so it must be unique within the plugin.
+    const TYPE_NAME: &'static str;
+
+    /// The plain value this stands for.
+    fn to_base_value(&self, span: Span) -> Result<Value, LabeledError>;
+}
+
+/// How to expand one registered [`PluginValue`] type from its bytes.
+struct Expander {
+    to_base_value: fn(&[u8], Span) -> Result<Value, LabeledError>,
+}
+
+static EXPANDERS: Lazy<Mutex<HashMap<&'static str, Expander>>> = Lazy::new(Default::default);
+
+fn decode<T: PluginValue>(data: &[u8], span: Span) -> Result<T, LabeledError> {
+    bincode::deserialize(data).map_err(|e| {
+        Error::new(format!("Could not decode a `{}` value", T::TYPE_NAME))
+            .source(e)
+            .span(span)
+            .into()
+    })
+}
+
+/// Lets this process expand `T` values, see the [module docs](self).
+pub fn register<T: PluginValue>() {
+    if let Ok(mut expanders) = EXPANDERS.lock() {
+        expanders.entry(T::TYPE_NAME).or_insert(Expander {
+            to_base_value: |data, span| decode::<T>(data, span)?.to_base_value(span),
+        });
+    }
+}
+
+/// Wraps `value` into an opaque nushell value.
+pub fn into_value<T: PluginValue>(value: &T, span: Span) -> Result<Value, LabeledError> {
+    register::<T>();
+    let data = bincode::serialize(value).map_err(|e| {
+        LabeledError::from(
+            Error::new(format!("Could not encode a `{}` value", T::TYPE_NAME))
+                .source(e)
+                .span(span),
+        )
+    })?;
+    Ok(Value::CustomValue {
+        val: Box::new(Handle {
+            type_name: T::TYPE_NAME.to_string(),
+            data,
+        }),
+        span,
+    })
+}
+
+/// Turns a value made by [`into_value`] back into `T`, for commands taking
+/// it as input.
+pub fn from_value<T: PluginValue>(value: &Value) -> Result<T, LabeledError> {
+    let (handle, span) = match value {
+        Value::CustomValue { val, span } => (val.as_any().downcast_ref::<Handle>(), *span),
+        other => (None, other.span().unwrap_or_else(|_| Span::unknown())),
+    };
+    let got = match handle {
+        Some(handle) if handle.type_name == T::TYPE_NAME => return decode(&handle.data, span),
+        Some(handle) => format!("got a `{}` value", handle.type_name),
+        None => format!("got {:?}", value.get_type()),
+    };
+    Err(Error::new(format!("Expected a `{}` value", T::TYPE_NAME))
+        .label(got)
+        .span(span)
+        .into())
+}
+
+/// The one [`CustomValue`] type behind every [`PluginValue`]: the encoded
+/// value and the name of its type. Nushell needs custom values to be
+/// deserializable without knowing their Rust type, which rules out a
+/// generic wrapper.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Handle {
+    type_name: String,
+    data: Vec<u8>,
+}
+
+impl Handle {
+    fn expand<R>(&self, span: Span, f: impl FnOnce(&Expander) -> R) -> Result<R, LabeledError> {
+        let expanders = EXPANDERS
+            .lock()
+            .map_err(|_| LabeledError::from(Error::new("Custom value registry is poisoned").span(span)))?;
+        match expanders.get(self.type_name.as_str()) {
+            Some(expander) => Ok(f(expander)),
+            None => Err(Error::new(format!("Cannot expand a `{}` value", self.type_name))
+                .label("this plugin doesn't know the type")
+                .help("register it with `PluginServer::custom_value`")
+                .span(span)
+                .into()),
+        }
+    }
+}
+
+#[typetag::serde(name = "nap::Handle")]
+impl CustomValue for Handle {
+    fn clone_value(&self, span: Span) -> Value {
+        Value::CustomValue {
+            val: Box::new(self.clone()),
+            span,
+        }
+    }
+
+    fn value_string(&self) -> String {
+        self.type_name.clone()
+    }
+
+    fn to_base_value(&self, span: Span) -> Result<Value, ShellError> {
+        self.expand(span, |expander| (expander.to_base_value)(&self.data, span))?
+            .map_err(ShellError::from)
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
@@ -0,0 +1,443 @@
+//! A serde [`Deserializer`](serde::Deserializer) that reads nushell values,
+//! so pipeline input and record arguments can be turned into Rust types
+//! without walking them by hand.
+//!
+//! Errors point at the nested value that didn't fit, with both its span and
+//! a description such as "row 3, column `id`".
+use std::fmt;
+
+use nu_protocol::{Span, Value};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+
+use crate::Error;
+
+/// Reads a `T` out of `value`.
+pub fn from_value<T: DeserializeOwned>(value: &Value) -> Result<T, Error> {
+    T::deserialize(Deserializer::new(value))
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::new(msg.to_string())
+    }
+}
+
+#[derive(Clone)]
+enum Segment {
+    Row(usize),
+    Column(String),
+}
+
+/// Where in the original value we are, such as row 3, column `id`.
+#[derive(Clone, Default)]
+struct Path(Vec<Segment>);
+
+impl Path {
+    fn join(&self, segment: Segment) -> Path {
+        let mut path = self.clone();
+        path.0.push(segment);
+        path
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "the value");
+        }
+        let segments = self
+            .0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Row(idx) => format!("row {}", idx),
+                Segment::Column(col) => format!("column `{}`", col),
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", segments.join(", "))
+    }
+}
+
+pub struct Deserializer<'a> {
+    value: &'a Value,
+    path: Path,
+}
+
+impl<'a> Deserializer<'a> {
+    pub fn new(value: &'a Value) -> Self {
+        Deserializer { value, path: Path::default() }
+    }
+
+    fn child(&self, value: &'a Value, segment: Segment) -> Self {
+        Deserializer { value, path: self.path.join(segment) }
+    }
+
+    fn span(&self) -> Span {
+        self.value.span().unwrap_or_else(|_| Span::unknown())
+    }
+
+    fn mismatch(&self, expected: &str) -> Error {
+        Error::new("Unexpected type")
+            .label(format!(
+                "{} was expected to be {}, but is {}",
+                self.path,
+                expected,
+                self.value.get_type()
+            ))
+            .span(self.span())
+    }
+
+    /// Points errors raised by serde itself (missing fields, out of range
+    /// numbers and the like) at the value we were reading when they
+    /// happened. Errors from further down already know where they are.
+    fn locate(span: Span, path: &Path, mut error: Error) -> Error {
+        if error.span.is_none() {
+            error.label = Some(format!("in {}", path));
+            error.span = Some(span);
+        }
+        error
+    }
+
+    fn any<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Value::Bool { val, .. } => visitor.visit_bool(*val),
+            Value::Int { val, .. }
+            | Value::Filesize { val, .. }
+            | Value::Duration { val, .. } => visitor.visit_i64(*val),
+            Value::Float { val, .. } => visitor.visit_f64(*val),
+            Value::Date { val, .. } => visitor.visit_string(val.to_rfc3339()),
+            Value::String { val, .. } => visitor.visit_str(val),
+            Value::Binary { val, .. } => visitor.visit_bytes(val),
+            Value::Nothing { .. } => visitor.visit_unit(),
+            Value::List { vals, .. } => visitor.visit_seq(ListAccess {
+                de: &self,
+                vals: vals.iter().enumerate(),
+            }),
+            Value::Record { cols, vals, .. } => visitor.visit_map(RecordAccess {
+                de: &self,
+                entries: cols.iter().zip(vals.iter()),
+                next: None,
+            }),
+            Value::CustomValue { val, span } => {
+                let base = val.to_base_value(*span)?;
+                Deserializer { value: &base, path: self.path.clone() }.any(visitor)
+            }
+            Value::Error { error } => Err(Error::from((**error).clone())),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.mismatch("a plain value")),
+        }
+    }
+
+    fn int<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Value::Int { .. } | Value::Filesize { .. } | Value::Duration { .. } => self.any(visitor),
+            // Lets integer fields holding Unix timestamps read dates back.
+            Value::Date { val, .. } => visitor.visit_i64(val.timestamp()),
+            _ => Err(self.mismatch("an int")),
+        }
+    }
+
+    fn float<'de, V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Value::Float { .. } | Value::Int { .. } => self.any(visitor),
+            _ => Err(self.mismatch("a float")),
+        }
+    }
+
+    fn expect<'de, V: Visitor<'de>>(self, matches: bool, expected: &str, visitor: V) -> Result<V::Value, Error> {
+        if matches {
+            self.any(visitor)
+        } else {
+            Err(self.mismatch(expected))
+        }
+    }
+}
+
+/// Runs `f` against `de`, then points any error it raised at `de`'s value.
+macro_rules! located {
+    ($de:ident, $f:expr) => {{
+        let span = $de.span();
+        let path = $de.path.clone();
+        $f.map_err(|e| Deserializer::locate(span, &path, e))
+    }};
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Deserializer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.any(visitor))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let matches = matches!(self.value, Value::Bool { .. });
+        located!(self, self.expect(matches, "a bool", visitor))
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.int(visitor))
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.int(visitor))
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.int(visitor))
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.int(visitor))
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.int(visitor))
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.int(visitor))
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.int(visitor))
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.int(visitor))
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.float(visitor))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        located!(self, self.float(visitor))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let matches = matches!(self.value, Value::String { .. } | Value::Date { .. });
+        located!(self, self.expect(matches, "a string", visitor))
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let matches = matches!(self.value, Value::Binary { .. } | Value::String { .. });
+        located!(self, self.expect(matches, "binary", visitor))
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.value {
+            Value::Nothing { .. } => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let matches = matches!(self.value, Value::Nothing { .. });
+        located!(self, self.expect(matches, "nothing", visitor))
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let matches = matches!(self.value, Value::List { .. });
+        located!(self, self.expect(matches, "a list", visitor))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let matches = matches!(self.value, Value::Record { .. });
+        located!(self, self.expect(matches, "a record", visitor))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        // Unit variants are plain strings, and everything else is a record
+        // with a single column named after the variant, mirroring `to_value`.
+        let result = match self.value {
+            Value::String { val, .. } => visitor.visit_enum(val.as_str().into_deserializer()),
+            Value::Record { cols, vals, .. } if cols.len() == 1 => visitor.visit_enum(VariantAccess {
+                variant: &cols[0],
+                value: self.child(&vals[0], Segment::Column(cols[0].clone())),
+            }),
+            _ => Err(self.mismatch("a string or a record with a single column")),
+        };
+        located!(self, result)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+}
+
+struct ListAccess<'d, 'a, I> {
+    de: &'d Deserializer<'a>,
+    vals: I,
+}
+
+impl<'de, 'd, 'a, I: Iterator<Item = (usize, &'a Value)>> de::SeqAccess<'de> for ListAccess<'d, 'a, I> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+        match self.vals.next() {
+            Some((idx, val)) => seed.deserialize(self.de.child(val, Segment::Row(idx))).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+struct RecordAccess<'d, 'a, I> {
+    de: &'d Deserializer<'a>,
+    entries: I,
+    next: Option<(&'a String, &'a Value)>,
+}
+
+impl<'de, 'd, 'a, I: Iterator<Item = (&'a String, &'a Value)>> de::MapAccess<'de> for RecordAccess<'d, 'a, I> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Error> {
+        match self.entries.next() {
+            Some((col, val)) => {
+                self.next = Some((col, val));
+                seed.deserialize(IntoDeserializer::<Error>::into_deserializer(col.as_str())).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (col, val) = self
+            .next
+            .take()
+            .ok_or_else(|| Error::new("Record value requested before its column"))?;
+        seed.deserialize(self.de.child(val, Segment::Column(col.clone())))
+    }
+}
+
+struct VariantAccess<'a> {
+    variant: &'a str,
+    value: Deserializer<'a>,
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for VariantAccess<'a> {
+    type Error = Error;
+    type Variant = Deserializer<'a>;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant), Error> {
+        let variant = seed.deserialize(IntoDeserializer::<Error>::into_deserializer(self.variant))?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for Deserializer<'a> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Row {
+        id: i64,
+        name: Option<String>,
+    }
+
+    fn span(start: usize) -> Span {
+        Span::new(start, start + 1)
+    }
+
+    fn row(id: Value, span: Span) -> Value {
+        Value::Record { cols: vec!["id".into()], vals: vec![id], span }
+    }
+
+    #[test]
+    fn records_read_into_structs() {
+        let rows = Value::List { vals: vec![row(Value::int(7, span(1)), span(0))], span: span(0) };
+        assert_eq!(from_value::<Vec<Row>>(&rows).unwrap(), vec![Row { id: 7, name: None }]);
+    }
+
+    #[test]
+    fn mismatches_point_at_the_nested_value() {
+        let rows = Value::List {
+            vals: vec![row(Value::int(7, span(1)), span(0)), row(Value::string("x", span(5)), span(4))],
+            span: span(0),
+        };
+        let err = from_value::<Vec<Row>>(&rows).unwrap_err();
+        assert_eq!(err.message, "Unexpected type");
+        assert!(err.label.unwrap().starts_with("row 1, column `id` was expected to be an int"));
+        assert_eq!(err.span, Some(span(5)));
+    }
+
+    #[test]
+    fn serde_errors_point_at_the_value_being_read() {
+        let rows = Value::List {
+            vals: vec![Value::Record { cols: vec![], vals: vec![], span: span(3) }],
+            span: span(0),
+        };
+        let err = from_value::<Vec<Row>>(&rows).unwrap_err();
+        assert_eq!(err.message, "missing field `id`");
+        assert_eq!(err.label.as_deref(), Some("in row 0"));
+        assert_eq!(err.span, Some(span(3)));
+    }
+
+    #[test]
+    fn unknown_variants_are_errors() {
+        #[derive(Deserialize, Debug)]
+        enum Color {
+            Red,
+        }
+        assert!(from_value::<Color>(&Value::string("Red", span(0))).is_ok());
+        let err = from_value::<Color>(&Value::string("Blue", span(2))).unwrap_err();
+        assert!(err.message.contains("unknown variant `Blue`"));
+        assert_eq!(err.span, Some(span(2)));
+    }
+}
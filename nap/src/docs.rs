@@ -0,0 +1,193 @@
+//! Reference documentation generated from command signatures, so the docs
+//! say exactly what `#[usage]` and friends say.
+//!
+//! Plugin binaries write these pages when started with
+//! `--generate-docs <dir>`.
+use std::fs;
+use std::path::Path;
+
+use nu_protocol::{Flag, PluginSignature, PositionalArg, Signature};
+
+use crate::{cli, completions, Error};
+
+/// A generated documentation file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    /// The file name, such as `mtg-search.md`.
+    pub name: String,
+    pub contents: String,
+}
+
+fn file_stem(sig: &Signature) -> String {
+    sig.name.split_whitespace().collect::<Vec<_>>().join("-")
+}
+
+/// `mtg search <name> [set] ...rest {flags}`, as nushell's help shows it.
+pub(crate) fn usage_line(sig: &Signature) -> String {
+    let mut usage = sig.name.clone();
+    for arg in &sig.required_positional {
+        usage.push_str(&format!(" <{}>", arg.name));
+    }
+    for arg in &sig.optional_positional {
+        usage.push_str(&format!(" ({})", arg.name));
+    }
+    if let Some(ref arg) = sig.rest_positional {
+        usage.push_str(&format!(" ...{}", arg.name));
+    }
+    usage.push_str(" {flags}");
+    usage
+}
+
+fn positionals(sig: &Signature) -> impl Iterator<Item = (&PositionalArg, &'static str)> {
+    let required = sig.required_positional.iter().map(|arg| (arg, ""));
+    let optional = sig.optional_positional.iter().map(|arg| (arg, "optional"));
+    let rest = sig.rest_positional.iter().map(|arg| (arg, "rest"));
+    required.chain(optional).chain(rest)
+}
+
+fn flag_name(flag: &Flag) -> String {
+    match flag.short {
+        Some(short) => format!("--{}, -{}", flag.long, short),
+        None => format!("--{}", flag.long),
+    }
+}
+
+fn markdown_page(signature: &PluginSignature) -> Page {
+    let sig = &signature.sig;
+    let mut md = format!("# `{}`\n\n", sig.name);
+    if !sig.usage.is_empty() {
+        md.push_str(&format!("{}\n\n", sig.usage));
+    }
+    if !sig.extra_usage.is_empty() {
+        md.push_str(&format!("{}\n\n", sig.extra_usage));
+    }
+    md.push_str(&format!("## Usage\n\n```nushell\n> {}\n```\n\n", usage_line(sig)));
+
+    if positionals(sig).next().is_some() {
+        md.push_str("## Parameters\n\n");
+        for (arg, kind) in positionals(sig) {
+            let kind = if kind.is_empty() { String::new() } else { format!(", {}", kind) };
+            md.push_str(&format!("- `{}` (`{}`{}): {}\n", arg.name, arg.shape, kind, arg.desc));
+        }
+        md.push('\n');
+    }
+
+    if !sig.named.is_empty() {
+        md.push_str("## Flags\n\n");
+        for flag in &sig.named {
+            let arg = flag.arg.as_ref().map(|shape| format!(" `<{}>`", shape)).unwrap_or_default();
+            md.push_str(&format!("- `{}`{}: {}\n", flag_name(flag), arg, flag.desc));
+        }
+        md.push('\n');
+    }
+
+    if !sig.input_output_types.is_empty() {
+        md.push_str("## Input/output types\n\n| input | output |\n| --- | --- |\n");
+        for (input, output) in &sig.input_output_types {
+            md.push_str(&format!("| {} | {} |\n", input, output));
+        }
+        md.push('\n');
+    }
+
+    if !signature.examples.is_empty() {
+        md.push_str("## Examples\n\n");
+        for example in &signature.examples {
+            md.push_str(&format!("{}\n\n```nushell\n> {}\n```\n\n", example.description, example.example));
+            if let Some(ref result) = example.result {
+                md.push_str(&format!("Result:\n\n```nushell\n{}\n```\n\n", cli::nuon(result)));
+            }
+        }
+    }
+
+    Page {
+        name: format!("{}.md", file_stem(sig)),
+        contents: md.trim_end().to_string() + "\n",
+    }
+}
+
+/// Renders one Markdown page per command, plus an `index.md` linking to
+/// each of them.
+pub fn render_markdown(signatures: &[PluginSignature]) -> Vec<Page> {
+    let mut index = "# Commands\n\n| command | description |\n| --- | --- |\n".to_string();
+    let mut pages = vec![];
+    for signature in signatures.iter().filter(|sig| !completions::is_completer(sig)) {
+        let page = markdown_page(signature);
+        index.push_str(&format!("| [`{}`]({}) | {} |\n", signature.sig.name, page.name, signature.sig.usage));
+        pages.push(page);
+    }
+    pages.push(Page { name: "index.md".to_string(), contents: index });
+    pages
+}
+
+/// Escapes `text` for use as roff text.
+fn roff(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    escaped
+        .lines()
+        .map(|line| if line.starts_with('.') || line.starts_with('\'') { format!("\\&{}", line) } else { line.to_string() })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn man_page(signature: &PluginSignature) -> Page {
+    let sig = &signature.sig;
+    let mut man = format!(".TH \"{}\" 1\n", file_stem(sig).to_uppercase());
+    man.push_str(&format!(".SH NAME\n{} \\- {}\n", roff(&sig.name), roff(&sig.usage)));
+    man.push_str(&format!(".SH SYNOPSIS\n.B {}\n", roff(&usage_line(sig))));
+    if !sig.extra_usage.is_empty() {
+        man.push_str(&format!(".SH DESCRIPTION\n{}\n", roff(&sig.extra_usage)));
+    }
+
+    if positionals(sig).next().is_some() {
+        man.push_str(".SH PARAMETERS\n");
+        for (arg, kind) in positionals(sig) {
+            let kind = if kind.is_empty() { String::new() } else { format!(", {}", kind) };
+            man.push_str(&format!(".TP\n.B {}\n({}{}) {}\n", roff(&arg.name), arg.shape, kind, roff(&arg.desc)));
+        }
+    }
+
+    if !sig.named.is_empty() {
+        man.push_str(".SH FLAGS\n");
+        for flag in &sig.named {
+            let arg = flag.arg.as_ref().map(|shape| format!(" \\fI<{}>\\fR", shape)).unwrap_or_default();
+            man.push_str(&format!(".TP\n\\fB{}\\fR{}\n{}\n", roff(&flag_name(flag)), arg, roff(&flag.desc)));
+        }
+    }
+
+    if !sig.input_output_types.is_empty() {
+        man.push_str(".SH INPUT/OUTPUT TYPES\n");
+        for (input, output) in &sig.input_output_types {
+            man.push_str(&format!(".TP\n{}\n{}\n", roff(&input.to_string()), roff(&output.to_string())));
+        }
+    }
+
+    if !signature.examples.is_empty() {
+        man.push_str(".SH EXAMPLES\n");
+        for example in &signature.examples {
+            man.push_str(&format!(".PP\n{}\n.PP\n.RS\n.nf\n> {}\n", roff(&example.description), roff(&example.example)));
+            if let Some(ref result) = example.result {
+                man.push_str(&format!("{}\n", roff(&cli::nuon(result))));
+            }
+            man.push_str(".fi\n.RE\n");
+        }
+    }
+
+    Page { name: format!("{}.1", file_stem(sig)), contents: man }
+}
+
+/// Renders one roff man page per command, for section 1.
+pub fn render_man(signatures: &[PluginSignature]) -> Vec<Page> {
+    signatures.iter().filter(|sig| !completions::is_completer(sig)).map(man_page).collect()
+}
+
+/// Writes the Markdown and man pages for `signatures` into `dir`, creating
+/// it if needed.
+pub fn write_docs(signatures: &[PluginSignature], dir: impl AsRef<Path>) -> Result<Vec<Page>, Error> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let pages = render_markdown(signatures).into_iter().chain(render_man(signatures)).collect::<Vec<_>>();
+    for page in &pages {
+        fs::write(dir.join(&page.name), &page.contents)?;
+    }
+    Ok(pages)
+}
@@ -0,0 +1,163 @@
+//! An error type that carries everything nushell can display (a headline,
+//! a label at a span, help text) together with the chain of errors that
+//! caused it, and renders all of that into a [`LabeledError`].
+use std::fmt;
+
+use nu_plugin::LabeledError;
+use nu_protocol::{ShellError, Span};
+
+type BoxedError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+pub struct Error {
+    pub(crate) message: String,
+    pub(crate) label: Option<String>,
+    pub(crate) help: Option<String>,
+    pub(crate) span: Option<Span>,
+    pub(crate) source: Option<BoxedError>,
+}
+
+impl Error {
+    /// Starts a new error with `message` as its headline.
+    pub fn new(message: impl Into<String>) -> Self {
+        Error {
+            message: message.into(),
+            label: None,
+            help: None,
+            span: None,
+            source: None,
+        }
+    }
+
+    /// Text shown next to the span, describing what is wrong there.
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// A hint on how to fix the problem.
+    pub fn help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// The error that caused this one. Its whole chain of sources is
+    /// included when the error is shown.
+    pub fn source(mut self, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Every error in the source chain, outermost first.
+    fn causes(&self) -> Vec<String> {
+        let mut causes = vec![];
+        let mut next = self.source.as_deref().map(|e| e as &(dyn std::error::Error + 'static));
+        while let Some(cause) = next {
+            causes.push(cause.to_string());
+            next = cause.source();
+        }
+        causes
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Error")
+            .field("message", &self.message)
+            .field("label", &self.label)
+            .field("help", &self.help)
+            .field("span", &self.span)
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl From<Error> for LabeledError {
+    fn from(error: Error) -> Self {
+        let mut details = vec![];
+        if let Some(ref label) = error.label {
+            details.push(label.clone());
+        }
+        details.extend(error.causes().into_iter().map(|c| format!("caused by: {}", c)));
+        if let Some(ref help) = error.help {
+            details.push(format!("help: {}", help));
+        }
+
+        // Nushell shows `msg` under the span, or as help text when there's
+        // no span, so the details go there and the headline stays short.
+        LabeledError {
+            msg: if details.is_empty() {
+                error.message.clone()
+            } else {
+                details.join("\n")
+            },
+            label: error.message,
+            span: error.span,
+        }
+    }
+}
+
+impl From<LabeledError> for Error {
+    fn from(error: LabeledError) -> Self {
+        Error {
+            message: error.label,
+            label: Some(error.msg),
+            help: None,
+            span: error.span,
+            source: None,
+        }
+    }
+}
+
+impl From<ShellError> for Error {
+    fn from(error: ShellError) -> Self {
+        LabeledError::from(error).into()
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::new("I/O error").source(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::new("Invalid JSON").source(error)
+    }
+}
+
+#[cfg(feature = "scryfall")]
+impl From<scryfall::Error> for Error {
+    fn from(error: scryfall::Error) -> Self {
+        Error::new("Scryfall request failed").source(error)
+    }
+}
+
+/// Attaches a span to the error of a `Result`, as in
+/// `Card::named(&name).await.at(call.head)?`.
+pub trait Spanned<T> {
+    fn at(self, span: Span) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> Spanned<T> for Result<T, E> {
+    fn at(self, span: Span) -> Result<T, Error> {
+        self.map_err(|e| e.into().span(span))
+    }
+}
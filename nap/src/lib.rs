@@ -1,19 +1,91 @@
+mod args;
+pub mod cache;
+mod cli;
+pub mod compat;
+pub mod config;
+pub mod custom;
+pub mod completions;
+pub mod de;
+pub mod docs;
+mod error;
+pub mod logging;
+pub mod middleware;
+mod panic;
+pub mod protocol;
+pub mod runtime;
+pub mod ser;
+mod server;
+pub mod stream;
+pub mod testing;
+pub mod value;
+
+pub use error::{Error, Spanned};
+pub use protocol::Encoding;
+pub use de::from_value;
+pub use middleware::Middleware;
+pub use ser::to_value;
+pub use server::PluginServer;
+pub use stream::PipelineOutput;
+pub use value::IntoValue;
+
+use std::future::Future;
 use std::marker::PhantomData;
+use std::time::Duration;
 
-use nu_plugin::{EvaluatedCall, Plugin as NuPlugin, LabeledError, MsgPackSerializer};
+use nu_plugin::{EvaluatedCall, LabeledError};
 use nu_protocol::{PluginSignature as NuPluginSignature, Value};
 
 pub trait PluginSignatures: Sized {
     fn signature() -> Vec<NuPluginSignature>;
     fn parse_call(name: &str, call: &EvaluatedCall) -> Result<Self, LabeledError>;
+
+    /// Runs the completer behind `name`, if it's one of the hidden
+    /// completion commands added for `#[complete(...)]` fields. See
+    /// [`completions`].
+    fn complete(name: &str, call: &EvaluatedCall) -> Option<Result<Vec<String>, LabeledError>> {
+        let _ = (name, call);
+        None
+    }
+
+    /// How long `name` may run, from its `#[timeout(...)]`. See
+    /// [`runtime::block_on_with_deadline`].
+    fn timeout(name: &str) -> Option<Duration> {
+        let _ = name;
+        None
+    }
+
+    /// How long results of `name` are kept, from its `#[cache(...)]`. See
+    /// [`cache`].
+    fn cache_ttl(name: &str) -> Option<Duration> {
+        let _ = name;
+        None
+    }
+}
+
+/// Routes a parsed call to the matching method of a handler. The derive
+/// implements this for every `H` implementing the generated `<Enum>Handler`
+/// trait, so forgetting a command is a compile error rather than a missing
+/// match arm.
+pub trait Dispatch<H>: PluginSignatures {
+    fn dispatch(self, handler: &mut H, call: &EvaluatedCall, input: &Value) -> Result<PipelineOutput, LabeledError>;
 }
 
-struct Plugin<'a, T: PluginSignatures, F: FnMut(T, &Value) -> Result<Value, LabeledError>> {
-    data: PhantomData<T>,
-    main: &'a mut F
+/// What the protocol loop needs from a plugin. This is `nu_plugin::Plugin`,
+/// except that commands can answer with a stream.
+pub(crate) trait Commands {
+    fn signature(&self) -> Vec<NuPluginSignature>;
+    fn run(&mut self, name: &str, call: &EvaluatedCall, input: &Value) -> Result<PipelineOutput, LabeledError>;
 }
 
-impl <'a, T: PluginSignatures, F: FnMut(T, &Value) -> Result<Value, LabeledError>> NuPlugin for Plugin<'a, T, F> {
+/// One `PluginSignatures` enum together with the closure running its
+/// commands, and whatever state that closure keeps while serving.
+pub(crate) struct Plugin<T: PluginSignatures, S, O: Into<PipelineOutput>, F: FnMut(&mut S, T, &EvaluatedCall, &Value) -> Result<O, LabeledError>> {
+    pub(crate) data: PhantomData<T>,
+    pub(crate) state: S,
+    pub(crate) main: F,
+}
+
+impl <T: PluginSignatures, S, O: Into<PipelineOutput>, F: FnMut(&mut S, T, &EvaluatedCall, &Value) -> Result<O, LabeledError>> Commands for Plugin<T, S, O, F> {
     fn signature(&self) -> Vec<NuPluginSignature> {
         T::signature()
     }
@@ -23,16 +95,78 @@ impl <'a, T: PluginSignatures, F: FnMut(T, &Value) -> Result<Value, LabeledError
         name: &str,
         call: &EvaluatedCall,
         input: &nu_protocol::Value,
-    ) -> Result<nu_protocol::Value, nu_plugin::LabeledError> {
-        let data = T::parse_call(name, call)?;
-        (self.main)(data, input)
+    ) -> Result<PipelineOutput, nu_plugin::LabeledError> {
+        runtime::start_deadline(name, T::timeout(name));
+        panic::catch(name, call.head, || {
+            if let Some(completions) = T::complete(name, call) {
+                return Ok(completions?.to_value(call.head).into());
+            }
+            let data = T::parse_call(name, call)?;
+            let state = &mut self.state;
+            let main = &mut self.main;
+            let run = move || main(state, data, call, input).map(Into::into);
+            match T::cache_ttl(name) {
+                Some(ttl) => cache::run_cached(name, ttl, call, input, run),
+                None => run(),
+            }
+        })
+        // Errors that don't say where they happened point at the command.
+        .map_err(|e| LabeledError {
+            span: e.span.or(Some(call.head)),
+            ..e
+        })
     }
 }
 
-pub fn serve_plugin<T: PluginSignatures, F: FnMut(T, &Value) -> Result<Value, LabeledError>>(main: &mut F) {
-    let mut plugin = Plugin {
-        data: PhantomData,
-        main: main
-    };
-    nu_plugin::serve_plugin(&mut plugin, MsgPackSerializer {})
+pub fn serve_plugin<T: PluginSignatures, O: Into<PipelineOutput>, F: FnMut(T, &Value) -> Result<O, LabeledError>>(main: &mut F) {
+    PluginServer::new().add_main(main).serve()
+}
+
+/// Like [`serve_plugin`], but speaking `encoding` to nushell. Setting
+/// `NAP_ENCODING=json` or `NAP_ENCODING=msgpack` overrides the choice made
+/// here, which is handy for reading the traffic while debugging.
+pub fn serve_plugin_with_encoder<T: PluginSignatures, O: Into<PipelineOutput>, F: FnMut(T, &Value) -> Result<O, LabeledError>>(main: &mut F, encoding: Encoding) {
+    PluginServer::new().encoding(encoding).add_main(main).serve()
+}
+
+/// Serves a plugin whose closure gets state built by `init`, which runs once
+/// when the plugin process starts.
+///
+/// Under the 0.77 protocol nushell starts a fresh process for every call
+/// and the process exits after answering it, so `S` lives for exactly one
+/// call. Don't count on anything in it, such as an HTTP client or a logged
+/// in session, being reused by the next call; keep what has to outlive a
+/// call on disk (see [`cache`]) or in the system keyring.
+///
+/// A panic in `main` is reported to nushell as an error on that call,
+/// rather than as a plugin that stopped talking.
+pub fn serve_plugin_with_state<T, S, O, I, F>(init: I, main: &mut F)
+where
+    T: PluginSignatures,
+    O: Into<PipelineOutput>,
+    I: FnOnce() -> S,
+    F: FnMut(&mut S, T, &EvaluatedCall, &Value) -> Result<O, LabeledError>,
+{
+    PluginServer::new().add_with_state(init, main).serve()
+}
+
+/// Serves a plugin whose commands are handled by one method each on
+/// `handler`, rather than by a single closure matching on every variant.
+pub fn serve_plugin_with_handler<T: Dispatch<H>, H>(handler: &mut H) {
+    PluginServer::new().add_handler::<T, H>(handler).serve()
+}
+
+/// Serves a plugin whose entry point is an `async fn`. Every call is driven
+/// to completion on the runtime from [`runtime`], which is created on first
+/// use by the plugin process (so once per call under the 0.77 protocol).
+/// Calls running past their `#[timeout]`, or
+/// [`runtime::RuntimeConfig::default_timeout`], are dropped and fail.
+pub fn serve_plugin_async<T, O, F, Fut>(main: F)
+where
+    T: PluginSignatures,
+    O: Into<PipelineOutput>,
+    F: FnMut(T, Value) -> Fut,
+    Fut: Future<Output = Result<O, LabeledError>>,
+{
+    PluginServer::new().add_async(main).serve()
 }
@@ -0,0 +1,192 @@
+//! Logging that stays off the plugin protocol.
+//!
+//! Stdout belongs to nushell, so log records go to stderr, or to a file when
+//! stderr would clutter the terminal. Logging is off unless `NAP_LOG` is
+//! set, and [`PluginServer::serve`](crate::PluginServer::serve) sets it up
+//! once per process, so plugins don't need to.
+//!
+//! `NAP_LOG` takes `env_logger`-style directives, such as `debug` or
+//! `info,surf=debug`. Prefixing them with `file:`, as in `file:debug`,
+//! writes to `plugins/<binary>.log` under the nushell config directory
+//! instead, keeping the last few files as it rotates.
+//!
+//! Every record logged while a command runs names that command.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, Once};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// How large a log file may grow before it's rotated.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// How many rotated files to keep, as `<binary>.log.1` and up.
+const KEEP_FILES: usize = 3;
+
+static INIT: Once = Once::new();
+static COMMAND: Mutex<Option<String>> = Mutex::new(None);
+
+/// Records which command is running, for the context of log records.
+pub(crate) fn set_command(name: Option<&str>) {
+    if let Ok(mut command) = COMMAND.lock() {
+        *command = name.map(str::to_string);
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, file, size })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        for n in (1..KEEP_FILES).rev() {
+            let _ = fs::rename(numbered(n), numbered(n + 1));
+        }
+        fs::rename(&self.path, numbered(1))?;
+        *self = RotatingFile::open(self.path.clone())?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size + line.len() as u64 > MAX_FILE_SIZE {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+enum Sink {
+    Stderr,
+    File(RotatingFile),
+}
+
+struct Logger {
+    /// `(target prefix, level)` pairs; `None` applies to every target.
+    directives: Vec<(Option<String>, LevelFilter)>,
+    sink: Mutex<Sink>,
+}
+
+impl Logger {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        // The most specific directive wins.
+        self.directives
+            .iter()
+            .filter(|(prefix, _)| match prefix {
+                Some(prefix) => target.starts_with(prefix.as_str()),
+                None => true,
+            })
+            .max_by_key(|(prefix, _)| prefix.as_ref().map_or(0, String::len))
+            .map_or(LevelFilter::Off, |(_, level)| *level)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let context = match COMMAND.lock().ok().and_then(|command| command.clone()) {
+            Some(command) => format!(" command={:?}", command),
+            None => String::new(),
+        };
+        let line = format!(
+            "{} {:<5} {}{}: {}\n",
+            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+            record.level(),
+            record.target(),
+            context,
+            record.args()
+        );
+        if let Ok(mut sink) = self.sink.lock() {
+            let written = match *sink {
+                Sink::Stderr => io::stderr().write_all(line.as_bytes()),
+                Sink::File(ref mut file) => file.write_line(&line),
+            };
+            // There's nowhere left to report a failure to log.
+            let _ = written;
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut sink) = self.sink.lock() {
+            let _ = match *sink {
+                Sink::Stderr => io::stderr().flush(),
+                Sink::File(ref mut file) => file.file.flush(),
+            };
+        }
+    }
+}
+
+fn parse_directives(spec: &str) -> Vec<(Option<String>, LevelFilter)> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .filter_map(|directive| match directive.split_once('=') {
+            Some((target, level)) => Some((Some(target.to_string()), level.parse().ok()?)),
+            None => Some((None, directive.parse().ok()?)),
+        })
+        .collect()
+}
+
+/// Where `file:` logging writes: `plugins/<binary>.log` under the nushell
+/// config directory.
+pub fn log_file() -> Option<PathBuf> {
+    let binary = std::env::current_exe().ok()?.file_stem()?.to_string_lossy().into_owned();
+    Some(dirs::config_dir()?.join("nushell").join("plugins").join(format!("{}.log", binary)))
+}
+
+/// Sets up logging as `NAP_LOG` asks, the first time it's called. Later
+/// calls, and calls after some other logger has been installed, do
+/// nothing.
+pub fn init() {
+    INIT.call_once(|| {
+        let Ok(spec) = std::env::var("NAP_LOG") else {
+            return;
+        };
+        let (to_file, spec) = match spec.strip_prefix("file:") {
+            Some(spec) => (true, spec),
+            None => (false, spec.as_str()),
+        };
+        let directives = parse_directives(spec);
+        let max_level = directives.iter().map(|(_, level)| *level).max().unwrap_or(LevelFilter::Off);
+
+        let sink = if to_file {
+            match log_file().map(RotatingFile::open) {
+                Some(Ok(file)) => Sink::File(file),
+                _ => {
+                    eprintln!("Could not open the plugin log file, logging to stderr instead.");
+                    Sink::Stderr
+                }
+            }
+        } else {
+            Sink::Stderr
+        };
+
+        let logger = Logger { directives, sink: Mutex::new(sink) };
+        if log::set_boxed_logger(Box::new(logger)).is_ok() {
+            log::set_max_level(max_level);
+        }
+    });
+}
@@ -0,0 +1,119 @@
+//! Behavior that wraps every command, such as timing or logging, without
+//! touching the commands themselves.
+//!
+//! Register middleware with [`PluginServer::middleware`]:
+//!
+//! ```ignore
+//! PluginServer::new()
+//!     .middleware(Redact::new(Metrics::new("/tmp/syn-metrics.jsonl")).command("syn login"))
+//!     .add_handler::<SynPlugin, _>(&mut syn)
+//!     .serve()
+//! ```
+//!
+//! [`PluginServer::middleware`]: crate::PluginServer::middleware
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use nu_plugin::{EvaluatedCall, LabeledError};
+use nu_protocol::Value;
+
+/// Hooks run around every command. `before` hooks run in the order the
+/// middleware was registered, and `after` hooks in reverse order.
+pub trait Middleware {
+    fn before(&mut self, name: &str, call: &EvaluatedCall) {
+        let _ = (name, call);
+    }
+
+    /// Runs once the command has finished, with its result and how long it
+    /// took. List streams have been collected by then, so `elapsed`
+    /// includes producing every item.
+    fn after(&mut self, name: &str, result: &Result<Value, LabeledError>, elapsed: Duration) {
+        let _ = (name, result, elapsed);
+    }
+}
+
+impl<M: Middleware + ?Sized> Middleware for Box<M> {
+    fn before(&mut self, name: &str, call: &EvaluatedCall) {
+        (**self).before(name, call)
+    }
+
+    fn after(&mut self, name: &str, result: &Result<Value, LabeledError>, elapsed: Duration) {
+        (**self).after(name, result, elapsed)
+    }
+}
+
+/// Appends one JSON object per call to a file: the command, when it ran,
+/// how long it took in milliseconds, and its error, if any.
+pub struct Metrics {
+    path: PathBuf,
+}
+
+impl Metrics {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Metrics { path: path.into() }
+    }
+}
+
+impl Middleware for Metrics {
+    fn after(&mut self, name: &str, result: &Result<Value, LabeledError>, elapsed: Duration) {
+        let line = serde_json::json!({
+            "command": name,
+            "at": chrono::Local::now().to_rfc3339(),
+            "elapsed_ms": elapsed.as_secs_f64() * 1000.0,
+            "error": result.as_ref().err().map(|e| &e.label),
+        });
+        // Metrics are best effort; failing to record them shouldn't fail
+        // the call.
+        if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&self.path) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+/// Hides the arguments of some commands from the middleware it wraps, so
+/// that logs and metrics never see them. The command itself still gets
+/// its real arguments.
+pub struct Redact<M> {
+    inner: M,
+    commands: Vec<String>,
+}
+
+impl<M: Middleware> Redact<M> {
+    pub fn new(inner: M) -> Self {
+        Redact { inner, commands: vec![] }
+    }
+
+    /// Redacts the arguments of `name`.
+    pub fn command(mut self, name: impl Into<String>) -> Self {
+        self.commands.push(name.into());
+        self
+    }
+}
+
+fn redacted(value: &Value) -> Value {
+    Value::string("<redacted>", value.span().unwrap_or_else(|_| nu_protocol::Span::unknown()))
+}
+
+impl<M: Middleware> Middleware for Redact<M> {
+    fn before(&mut self, name: &str, call: &EvaluatedCall) {
+        if !self.commands.iter().any(|command| command == name) {
+            return self.inner.before(name, call);
+        }
+        let call = EvaluatedCall {
+            head: call.head,
+            positional: call.positional.iter().map(redacted).collect(),
+            named: call
+                .named
+                .iter()
+                .map(|(flag, value)| (flag.clone(), value.as_ref().map(redacted)))
+                .collect(),
+        };
+        self.inner.before(name, &call)
+    }
+
+    fn after(&mut self, name: &str, result: &Result<Value, LabeledError>, elapsed: Duration) {
+        self.inner.after(name, result, elapsed)
+    }
+}
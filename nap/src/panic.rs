@@ -0,0 +1,107 @@
+//! Keeps a panicking handler from taking the whole plugin process down.
+//!
+//! Without this, a panic kills the plugin and nushell only reports that the
+//! plugin stopped talking. Instead we catch the unwind, and report the panic
+//! message and location as a regular [`LabeledError`] on the command that
+//! panicked.
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+
+use nu_plugin::LabeledError;
+use nu_protocol::Span;
+
+thread_local! {
+    /// Whether this thread is inside [`catch`], which reports the panic
+    /// itself.
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    /// Where this thread's most recent caught panic happened, as recorded
+    /// by our panic hook. The payload passed to `catch_unwind` doesn't carry
+    /// a location, so the hook stashes it here for [`catch`] to pick up.
+    static LAST_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Wraps the current panic hook so that panics inside [`catch`] only record
+/// where they happened, instead of printing. Any other panic, such as one
+/// on a runtime worker thread, still goes to the previous hook.
+pub(crate) fn install_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !CATCHING.with(Cell::get) {
+            return previous(info);
+        }
+        let location = info
+            .location()
+            .map(|loc| format!("{}:{}:{}", loc.file(), loc.line(), loc.column()));
+        LAST_LOCATION.with(|last| *last.borrow_mut() = location);
+    }));
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+/// Runs `f`, converting a panic into a [`LabeledError`] naming `command`.
+pub(crate) fn catch<T>(
+    command: &str,
+    span: Span,
+    f: impl FnOnce() -> Result<T, LabeledError>,
+) -> Result<T, LabeledError> {
+    let outer = CATCHING.with(|catching| catching.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|catching| catching.set(outer));
+    result.unwrap_or_else(|payload| {
+        let location = LAST_LOCATION
+            .with(|last| last.borrow_mut().take())
+            .unwrap_or_else(|| "unknown location".to_string());
+        Err(LabeledError {
+            label: format!("`{}` panicked", command),
+            msg: format!(
+                "The plugin panicked while running `{}`: {} (at {})",
+                command,
+                payload_message(&*payload),
+                location
+            ),
+            span: Some(span),
+        })
+    })
+}
+
+/// Held by tests that replace the panic hook, and by tests that panic on
+/// purpose, since the hook is shared by every test thread.
+#[cfg(test)]
+pub(crate) static HOOK_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // The hook is process-wide, so everything touching it is one test.
+    #[test]
+    fn caught_panics_are_reported_and_others_reach_the_previous_hook() {
+        let _lock = HOOK_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let reached = Arc::new(AtomicBool::new(false));
+        let flag = reached.clone();
+        panic::set_hook(Box::new(move |_| flag.store(true, Ordering::SeqCst)));
+        install_hook();
+
+        let err = catch::<()>("mtg tutor", Span::unknown(), || panic!("boom")).unwrap_err();
+        assert_eq!(err.label, "`mtg tutor` panicked");
+        assert!(err.msg.contains("boom"), "{}", err.msg);
+        assert!(err.msg.contains("panic.rs"), "{}", err.msg);
+        assert!(!reached.load(Ordering::SeqCst));
+
+        let _ = std::thread::spawn(|| panic!("worker")).join();
+        assert!(reached.load(Ordering::SeqCst));
+
+        let _ = panic::take_hook();
+    }
+}
@@ -0,0 +1,299 @@
+//! The plugin side of nushell's plugin protocol.
+//!
+//! `nu_plugin::serve_plugin` hides the wire format entirely, which makes it
+//! hard to pick an encoding at runtime or to see what was actually sent. The
+//! message types here mirror `nu_plugin`'s own (and serialize identically),
+//! so that we can run the protocol loop ourselves.
+//!
+//! Setting `NAP_CAPTURE` to a file path appends every request and response
+//! frame to that file as one JSON object per line, which can be read back
+//! with [`read_capture`] for offline replay.
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use nu_plugin::{EvaluatedCall, LabeledError, PluginData};
+use nu_protocol::{CustomValue, PluginSignature, Value};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{cli, compat, completions, docs, panic, Commands, Error};
+
+#[derive(Serialize, Deserialize)]
+pub struct CallInfo {
+    pub name: String,
+    pub call: EvaluatedCall,
+    pub input: CallInput,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum CallInput {
+    Value(Value),
+    Data(PluginData),
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum PluginCall {
+    Signature,
+    CallInfo(CallInfo),
+    CollapseCustomValue(PluginData),
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum PluginResponse {
+    Error(LabeledError),
+    Signature(Vec<PluginSignature>),
+    Value(Box<Value>),
+    PluginData(String, PluginData),
+}
+
+impl PluginResponse {
+    /// A short description of the response, for error messages. Responses
+    /// can't be `Debug`, since `PluginSignature` isn't.
+    pub fn summary(&self) -> String {
+        match self {
+            PluginResponse::Error(e) => format!("an error: {}", e.label),
+            PluginResponse::Signature(signatures) => format!("{} signatures", signatures.len()),
+            PluginResponse::Value(value) => format!("a value of type {}", value.get_type()),
+            PluginResponse::PluginData(name, _) => format!("custom value `{}`", name),
+        }
+    }
+}
+
+/// How messages are encoded on stdin and stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Human readable, handy when debugging protocol issues.
+    Json,
+    /// Compact, and what plugins use by default.
+    MsgPack,
+}
+
+impl Encoding {
+    /// The name nushell expects in the encoding handshake.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MsgPack => "msgpack",
+        }
+    }
+
+    /// Reads the `NAP_ENCODING` override, if set to `json` or `msgpack`.
+    pub fn from_env() -> Option<Self> {
+        match std::env::var("NAP_ENCODING").ok()?.to_lowercase().as_str() {
+            "json" => Some(Encoding::Json),
+            "msgpack" => Some(Encoding::MsgPack),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, message: &T, writer: &mut impl Write) -> Result<(), Error> {
+        match self {
+            Encoding::Json => serde_json::to_writer(&mut *writer, message)?,
+            Encoding::MsgPack => rmp_serde::encode::write(&mut *writer, message)
+                .map_err(|e| Error::new("Failed to encode plugin message").source(e))?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, reader: &mut impl BufRead) -> Result<T, Error> {
+        match self {
+            Encoding::Json => Ok(serde_json::from_reader(reader)?),
+            Encoding::MsgPack => rmp_serde::from_read(reader)
+                .map_err(|e| Error::new("Failed to decode plugin message").source(e)),
+        }
+    }
+}
+
+/// A single message as recorded by `NAP_CAPTURE`.
+#[derive(Serialize, Deserialize)]
+pub enum Frame {
+    Call(PluginCall),
+    Response(PluginResponse),
+}
+
+/// A [`Frame`] borrowing its message, so recording doesn't need a copy.
+/// Serializes exactly like the `Frame` it mirrors.
+#[derive(Serialize)]
+#[serde(rename = "Frame")]
+enum FrameRef<'a> {
+    Call(&'a PluginCall),
+    Response(&'a PluginResponse),
+}
+
+struct Capture {
+    file: Option<File>,
+}
+
+impl Capture {
+    fn from_env() -> Self {
+        let file = std::env::var_os("NAP_CAPTURE").and_then(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .ok()
+        });
+        Capture { file }
+    }
+
+    fn record(&mut self, frame: FrameRef) {
+        // Capturing is a debugging aid, so failing to write the capture file
+        // shouldn't fail the call itself.
+        if let Some(ref mut file) = self.file {
+            let _ = serde_json::to_writer(&mut *file, &frame);
+            let _ = file.write_all(b"\n");
+        }
+    }
+}
+
+/// Reads back every frame recorded to a `NAP_CAPTURE` file, in order.
+pub fn read_capture(path: impl AsRef<Path>) -> Result<Vec<Frame>, Error> {
+    let reader = BufReader::new(File::open(path)?);
+    serde_json::Deserializer::from_reader(reader)
+        .into_iter::<Frame>()
+        .map(|frame| frame.map_err(Error::from))
+        .collect()
+}
+
+fn custom_value(data: &PluginData) -> Result<Box<dyn CustomValue>, Error> {
+    bincode::deserialize::<Box<dyn CustomValue>>(&data.data)
+        .map_err(|e| Error::new("Could not decode custom value").source(e).span(data.span))
+}
+
+fn respond(plugin: &mut impl Commands, call: PluginCall) -> PluginResponse {
+    match call {
+        PluginCall::Signature => PluginResponse::Signature(plugin.signature()),
+        PluginCall::CallInfo(info) => {
+            let input = match info.input {
+                CallInput::Value(value) => Ok(value),
+                CallInput::Data(data) => custom_value(&data)
+                    .map(|val| Value::CustomValue { val, span: data.span }),
+            };
+            // The protocol can't carry list streams yet, so buffer them here.
+            // Streams run lazily, so this can panic too.
+            let result = input
+                .map_err(LabeledError::from)
+                .and_then(|input| plugin.run(&info.name, &info.call, &input))
+                .and_then(|output| panic::catch(&info.name, info.call.head, || Ok(output.into_value())));
+            match result {
+                Ok(Value::CustomValue { val, span }) => match bincode::serialize(&val) {
+                    Ok(data) => PluginResponse::PluginData(val.value_string(), PluginData { data, span }),
+                    Err(e) => PluginResponse::Error(
+                        Error::new("Could not encode custom value").source(e).span(span).into()
+                    ),
+                },
+                Ok(value) => PluginResponse::Value(Box::new(value)),
+                Err(e) => PluginResponse::Error(e),
+            }
+        }
+        PluginCall::CollapseCustomValue(data) => {
+            match custom_value(&data).and_then(|val| Ok(val.to_base_value(data.span)?)) {
+                Ok(value) => PluginResponse::Value(Box::new(value)),
+                Err(e) => PluginResponse::Error(e.into()),
+            }
+        }
+    }
+}
+
+fn print_help(plugin: &impl Commands, encoding: Encoding) {
+    println!("Nushell plugin using the {} encoding.", encoding.name());
+    println!("Register it with `register <path-to-this-binary>`, or run a command");
+    println!("directly with `<path-to-this-binary> [--output table|json|nuon] [--config <file>] <command> [args]`.\n");
+    println!("Commands:");
+    for sig in plugin.signature().iter().filter(|sig| !completions::is_completer(sig)) {
+        println!("  {}\t{}", sig.sig.name, sig.sig.usage);
+    }
+}
+
+fn generate_docs(plugin: &impl Commands, dir: Option<&String>) -> i32 {
+    let Some(dir) = dir else {
+        eprintln!("error: --generate-docs needs a directory to write to");
+        return 2;
+    };
+    match docs::write_docs(&plugin.signature(), dir) {
+        Ok(pages) => {
+            for page in pages {
+                println!("{}", Path::new(dir).join(page.name).display());
+            }
+            0
+        }
+        Err(e) => {
+            cli::report("", &e.into());
+            1
+        }
+    }
+}
+
+fn generate_module(plugin: &impl Commands, dir: Option<&String>) -> i32 {
+    let Some(dir) = dir else {
+        eprintln!("error: --generate-module needs a directory to write to");
+        return 2;
+    };
+    let result = completions::render_module(&plugin.signature()).and_then(|page| {
+        let path = Path::new(dir).join(page.name);
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&path, page.contents)?;
+        Ok(path)
+    });
+    match result {
+        Ok(path) => {
+            println!("{}", path.display());
+            0
+        }
+        Err(e) => {
+            cli::report("", &e.into());
+            1
+        }
+    }
+}
+
+/// Answers a single request from nushell, which is all a plugin process is
+/// asked to do. Started with a command line instead, the plugin runs that
+/// command and prints the result, see [`cli`].
+pub(crate) fn serve(plugin: &mut impl Commands, encoding: Encoding) {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.first().map(String::as_str) == Some("--dump-signatures") {
+        println!("{}", compat::SignatureDump::new(&plugin.signature()).to_json());
+        std::process::exit(0)
+    }
+    if args.first().map(String::as_str) == Some("--generate-docs") {
+        std::process::exit(generate_docs(plugin, args.get(1)))
+    }
+    if args.first().map(String::as_str) == Some("--generate-module") {
+        std::process::exit(generate_module(plugin, args.get(1)))
+    }
+    if cli::requested(&args) {
+        std::process::exit(cli::run(plugin, &args))
+    }
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print_help(plugin, encoding);
+        std::process::exit(0)
+    }
+
+    let mut capture = Capture::from_env();
+    let mut stdout = io::stdout();
+
+    // Nushell first reads which encoding we speak, as a length-prefixed name.
+    let name = encoding.name();
+    let mut handshake = vec![name.len() as u8];
+    handshake.extend_from_slice(name.as_bytes());
+    if let Err(e) = stdout.write_all(&handshake).and_then(|_| stdout.flush()) {
+        eprintln!("Failed to send encoding to nushell: {}", e);
+        std::process::exit(1)
+    }
+
+    let response = match encoding.decode::<PluginCall>(&mut io::stdin().lock()) {
+        Ok(call) => {
+            capture.record(FrameRef::Call(&call));
+            respond(plugin, call)
+        }
+        Err(e) => PluginResponse::Error(e.into()),
+    };
+    capture.record(FrameRef::Response(&response));
+
+    if let Err(e) = encoding.encode(&response, &mut stdout) {
+        eprintln!("Failed to send response to nushell: {}", e);
+        std::process::exit(1)
+    }
+}
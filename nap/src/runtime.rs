@@ -0,0 +1,184 @@
+//! A single async runtime shared by everything a plugin process runs. Under
+//! the 0.77 protocol that is a single call, since nushell starts a fresh
+//! process for each one.
+//!
+//! The runtime is created lazily on first use, so plugins that never run
+//! async code don't pay for it. Call [`configure`] before the first call to
+//! [`block_on`] (typically at the top of `main`) to pick a different flavor
+//! or worker count.
+//!
+//! [`block_on_with_deadline`] also gives up once the running command's
+//! timeout passes: see `#[timeout("30s")]` and
+//! [`RuntimeConfig::default_timeout`].
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either};
+use nu_plugin::LabeledError;
+use once_cell::sync::OnceCell;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    /// Run everything on the thread that called [`block_on`].
+    CurrentThread,
+    /// Use a pool of worker threads.
+    MultiThread,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeConfig {
+    pub flavor: Flavor,
+    /// Number of worker threads for [`Flavor::MultiThread`]. `None` leaves
+    /// the choice to the runtime (usually one per core).
+    pub worker_threads: Option<usize>,
+    /// How long [`block_on_with_deadline`] waits for commands that don't
+    /// set their own `#[timeout]`. `None` waits forever.
+    pub default_timeout: Option<Duration>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig {
+            flavor: Flavor::MultiThread,
+            worker_threads: None,
+            default_timeout: None,
+        }
+    }
+}
+
+static CONFIG: OnceCell<RuntimeConfig> = OnceCell::new();
+
+/// Sets the configuration used to build the shared runtime. Returns the
+/// config back if the runtime was already configured or started.
+pub fn configure(config: RuntimeConfig) -> Result<(), RuntimeConfig> {
+    CONFIG.set(config)
+}
+
+fn config() -> &'static RuntimeConfig {
+    CONFIG.get_or_init(RuntimeConfig::default)
+}
+
+struct Deadline {
+    command: String,
+    started: Instant,
+    timeout: Duration,
+}
+
+static DEADLINE: Mutex<Option<Deadline>> = Mutex::new(None);
+
+/// Held by tests that depend on the deadline, since every test thread
+/// shares it.
+#[cfg(test)]
+pub(crate) static DEADLINE_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Starts the clock for `command`, which gives up after `timeout`, or after
+/// the configured default if that's `None`.
+pub(crate) fn start_deadline(command: &str, timeout: Option<Duration>) {
+    let deadline = timeout.or(config().default_timeout).map(|timeout| Deadline {
+        command: command.to_string(),
+        started: Instant::now(),
+        timeout,
+    });
+    if let Ok(mut current) = DEADLINE.lock() {
+        *current = deadline;
+    }
+}
+
+#[cfg(feature = "async-std")]
+async fn sleep(duration: Duration) {
+    async_std::task::sleep(duration).await
+}
+
+#[cfg(all(feature = "tokio", not(feature = "async-std")))]
+async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+/// Like [`block_on`], but gives up when the running command's timeout
+/// passes. `future` is dropped then, and the command fails with an error
+/// saying how long it ran.
+pub fn block_on_with_deadline<T, E, F>(future: F) -> Result<T, E>
+where
+    E: From<LabeledError>,
+    F: Future<Output = Result<T, E>>,
+{
+    let deadline = DEADLINE
+        .lock()
+        .ok()
+        .and_then(|current| current.as_ref().map(|d| (d.command.clone(), d.started, d.timeout)));
+    let Some((command, started, timeout)) = deadline else {
+        return try_block_on(future)?;
+    };
+    let remaining = timeout.saturating_sub(started.elapsed());
+    try_block_on(async move {
+        futures::pin_mut!(future);
+        let timer = sleep(remaining);
+        futures::pin_mut!(timer);
+        match future::select(future, timer).await {
+            Either::Left((output, _)) => output,
+            Either::Right(((), _)) => Err(LabeledError {
+                label: format!("`{}` timed out", command),
+                msg: format!("gave up after {:.1?}, the limit is {:?}", started.elapsed(), timeout),
+                span: None,
+            }
+            .into()),
+        }
+    })?
+}
+
+/// Runs `future` to completion on the shared runtime.
+///
+/// # Panics
+///
+/// If the runtime can't be started. There's no error to return here;
+/// [`block_on_with_deadline`] reports that as a regular error instead.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    try_block_on(future).unwrap_or_else(|e| panic!("{}: {}", e.label, e.msg))
+}
+
+#[cfg(feature = "async-std")]
+fn try_block_on<F: Future>(future: F) -> Result<F::Output, LabeledError> {
+    static STARTED: OnceCell<()> = OnceCell::new();
+    STARTED.get_or_init(|| {
+        // async-std only reads its thread count from the environment, and
+        // only when its global executor first starts.
+        let threads = match config().flavor {
+            Flavor::CurrentThread => Some(1),
+            Flavor::MultiThread => config().worker_threads,
+        };
+        if let Some(threads) = threads {
+            if std::env::var_os("ASYNC_STD_THREAD_COUNT").is_none() {
+                std::env::set_var("ASYNC_STD_THREAD_COUNT", threads.to_string());
+            }
+        }
+    });
+    Ok(async_std::task::block_on(future))
+}
+
+#[cfg(all(feature = "tokio", not(feature = "async-std")))]
+fn try_block_on<F: Future>(future: F) -> Result<F::Output, LabeledError> {
+    static RUNTIME: OnceCell<Result<tokio::runtime::Runtime, String>> = OnceCell::new();
+    let runtime = RUNTIME.get_or_init(|| {
+        let config = config();
+        let mut builder = match config.flavor {
+            Flavor::CurrentThread => tokio::runtime::Builder::new_current_thread(),
+            Flavor::MultiThread => tokio::runtime::Builder::new_multi_thread(),
+        };
+        if let Some(threads) = config.worker_threads {
+            builder.worker_threads(threads);
+        }
+        builder.enable_all().build().map_err(|e| e.to_string())
+    });
+    match runtime {
+        Ok(runtime) => Ok(runtime.block_on(future)),
+        Err(e) => Err(LabeledError {
+            label: "Failed to start the plugin's async runtime".into(),
+            msg: e.clone(),
+            span: None,
+        }),
+    }
+}
+
+#[cfg(not(any(feature = "tokio", feature = "async-std")))]
+compile_error!("nap needs either the `tokio` or the `async-std` feature enabled.");
@@ -0,0 +1,513 @@
+//! A serde [`Serializer`](serde::Serializer) that produces nushell values.
+//!
+//! Structs and maps become records, sequences and tuples become lists, and
+//! `None` and `()` become nothing. Enums follow serde's usual externally
+//! tagged layout: unit variants are strings, and other variants are a
+//! record with a single column named after the variant.
+//!
+//! Serde has no notion of a date, so fields that should come out as
+//! `Value::Date` need one of the helpers in [`date`] or [`unix_seconds`]:
+//!
+//! ```ignore
+//! #[derive(Serialize)]
+//! struct Note {
+//!     #[serde(serialize_with = "nap::ser::unix_seconds::serialize")]
+//!     ctime: u64,
+//! }
+//! ```
+use chrono::DateTime;
+use nu_protocol::{ShellError, Span, Value};
+use serde::ser::{self, Serialize};
+
+use crate::Error;
+
+/// Newtype struct name that the date helpers wrap an RFC 3339 string in, so
+/// that [`Serializer`] can tell it apart from any other string.
+pub(crate) const DATE_TOKEN: &str = "$nap::Date";
+
+/// Converts `value` into a nushell value, with every part of it at `span`.
+/// If `value` fails to serialize, the result is a `Value::Error` describing
+/// why.
+pub fn to_value<T: Serialize + ?Sized>(value: &T, span: Span) -> Value {
+    value
+        .serialize(Serializer { span })
+        .unwrap_or_else(|e| Value::Error {
+            error: Box::new(ShellError::from(nu_plugin::LabeledError::from(e.span(span)))),
+        })
+}
+
+impl ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::new(msg.to_string())
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Serializer {
+    pub span: Span,
+}
+
+impl Serializer {
+    fn tagged(self, variant: &str, value: Value) -> Value {
+        Value::Record {
+            cols: vec![variant.to_string()],
+            vals: vec![value],
+            span: self.span,
+        }
+    }
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = Error;
+
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeVariant<SerializeList>;
+    type SerializeMap = SerializeRecord;
+    type SerializeStruct = SerializeRecord;
+    type SerializeStructVariant = SerializeVariant<SerializeRecord>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(Value::boolean(v, self.span))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        Ok(Value::int(v.into(), self.span))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        Ok(Value::int(v.into(), self.span))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        Ok(Value::int(v.into(), self.span))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        Ok(Value::int(v, self.span))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        Ok(Value::int(v.into(), self.span))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        Ok(Value::int(v.into(), self.span))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        Ok(Value::int(v.into(), self.span))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        Ok(match i64::try_from(v) {
+            Ok(int) => Value::int(int, self.span),
+            Err(_) => Value::float(v as f64, self.span),
+        })
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Value, Error> {
+        Ok(match i64::try_from(v) {
+            Ok(int) => Value::int(int, self.span),
+            Err(_) => Value::float(v as f64, self.span),
+        })
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Value, Error> {
+        Ok(match i64::try_from(v) {
+            Ok(int) => Value::int(int, self.span),
+            Err(_) => Value::float(v as f64, self.span),
+        })
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        Ok(Value::float(v.into(), self.span))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(Value::float(v, self.span))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        Ok(Value::string(v.to_string(), self.span))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        Ok(Value::string(v, self.span))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        Ok(Value::Binary { val: v.to_vec(), span: self.span })
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::nothing(self.span))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::nothing(self.span))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+        Ok(Value::nothing(self.span))
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Value, Error> {
+        Ok(Value::string(variant, self.span))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, name: &'static str, value: &T) -> Result<Value, Error> {
+        let value = value.serialize(self)?;
+        if name != DATE_TOKEN {
+            return Ok(value);
+        }
+        let date = DateTime::parse_from_rfc3339(&value.as_string()?)
+            .map_err(|e| Error::new("Invalid date").source(e))?;
+        Ok(Value::Date { val: date, span: self.span })
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32, variant: &'static str, value: &T) -> Result<Value, Error> {
+        Ok(self.tagged(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, Error> {
+        Ok(SerializeList {
+            vals: Vec::with_capacity(len.unwrap_or(0)),
+            ser: self,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str, len: usize) -> Result<SerializeVariant<SerializeList>, Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeRecord, Error> {
+        Ok(SerializeRecord {
+            cols: Vec::with_capacity(len.unwrap_or(0)),
+            vals: Vec::with_capacity(len.unwrap_or(0)),
+            ser: self,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeRecord, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str, len: usize) -> Result<SerializeVariant<SerializeRecord>, Error> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+pub struct SerializeList {
+    vals: Vec<Value>,
+    ser: Serializer,
+}
+
+impl SerializeList {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.vals.push(value.serialize(self.ser)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Value {
+        Value::List { vals: self.vals, span: self.ser.span }
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+pub struct SerializeRecord {
+    cols: Vec<String>,
+    vals: Vec<Value>,
+    ser: Serializer,
+}
+
+impl SerializeRecord {
+    fn finish(self) -> Value {
+        Value::Record { cols: self.cols, vals: self.vals, span: self.ser.span }
+    }
+}
+
+impl ser::SerializeMap for SerializeRecord {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        // Record columns are always strings, so render other keys (numbers,
+        // unit variants and the like) the way nushell would print them.
+        let col = match key.serialize(self.ser)? {
+            Value::String { val, .. } => val,
+            Value::Int { val, .. } => val.to_string(),
+            Value::Float { val, .. } => val.to_string(),
+            Value::Bool { val, .. } => val.to_string(),
+            other => return Err(Error::new(format!(
+                "Record columns must be strings, but got a key of type {}",
+                other.get_type()
+            ))),
+        };
+        self.cols.push(col);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.vals.push(value.serialize(self.ser)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for SerializeRecord {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.cols.push(key.to_string());
+        self.vals.push(value.serialize(self.ser)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(self.finish())
+    }
+}
+
+/// Collects the contents of a tuple or struct variant, then wraps them in a
+/// record keyed by the variant name.
+pub struct SerializeVariant<T> {
+    variant: &'static str,
+    inner: T,
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeList> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.inner.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        let ser = self.inner.ser;
+        Ok(ser.tagged(self.variant, self.inner.finish()))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeRecord> {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        let ser = self.inner.ser;
+        Ok(ser.tagged(self.variant, self.inner.finish()))
+    }
+}
+
+/// Serializes any chrono `DateTime` as a `Value::Date`. Use with
+/// `#[serde(serialize_with = "nap::ser::date::serialize")]`.
+pub mod date {
+    use chrono::{DateTime, TimeZone};
+    use serde::Serializer;
+
+    use super::DATE_TOKEN;
+
+    pub fn serialize<Tz: TimeZone, S: Serializer>(date: &DateTime<Tz>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(DATE_TOKEN, &date.to_rfc3339())
+    }
+}
+
+/// Serializes an integer count of seconds since the Unix epoch as a
+/// `Value::Date`. Use with
+/// `#[serde(serialize_with = "nap::ser::unix_seconds::serialize")]`.
+pub mod unix_seconds {
+    use chrono::{TimeZone, Utc};
+    use serde::{ser::Error, Serializer};
+
+    pub fn serialize<T: Copy + TryInto<i64>, S: Serializer>(secs: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        let date = (*secs)
+            .try_into()
+            .ok()
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+            .ok_or_else(|| S::Error::custom("Timestamp out of range"))?;
+        super::date::serialize(&date, serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{FixedOffset, TimeZone};
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::from_value;
+
+    fn span() -> Span {
+        Span::new(0, 1)
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Row {
+        id: i64,
+        name: Option<String>,
+        tags: Vec<String>,
+        done: (),
+    }
+
+    #[test]
+    fn structs_round_trip_through_records() {
+        let row = Row { id: 7, name: None, tags: vec!["a".into()], done: () };
+        let value = to_value(&row, span());
+        match &value {
+            Value::Record { cols, vals, .. } => {
+                assert_eq!(cols, &["id", "name", "tags", "done"]);
+                assert!(matches!(vals[1], Value::Nothing { .. }));
+                assert!(matches!(vals[3], Value::Nothing { .. }));
+            }
+            other => panic!("Expected a record, but got {:?}", other),
+        }
+        assert_eq!(from_value::<Row>(&value).unwrap(), row);
+
+        let row = Row { name: Some("Opt".into()), ..row };
+        assert_eq!(from_value::<Row>(&to_value(&row, span())).unwrap(), row);
+    }
+
+    #[test]
+    fn dates_become_date_values() {
+        #[derive(Serialize)]
+        struct Note {
+            #[serde(serialize_with = "date::serialize")]
+            mtime: chrono::DateTime<FixedOffset>,
+            #[serde(serialize_with = "unix_seconds::serialize")]
+            ctime: u64,
+        }
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Read {
+            mtime: String,
+            ctime: u64,
+        }
+
+        let mtime = FixedOffset::east_opt(3600).unwrap().with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap();
+        let value = to_value(&Note { mtime, ctime: 1_680_000_000 }, span());
+        match &value {
+            Value::Record { vals, .. } => {
+                assert_eq!(vals[0], Value::Date { val: mtime, span: span() });
+                assert!(matches!(&vals[1], Value::Date { val, .. } if val.timestamp() == 1_680_000_000));
+            }
+            other => panic!("Expected a record, but got {:?}", other),
+        }
+        let read = from_value::<Read>(&value).unwrap();
+        assert_eq!(read, Read { mtime: mtime.to_rfc3339(), ctime: 1_680_000_000 });
+    }
+
+    #[test]
+    fn out_of_range_timestamps_are_errors() {
+        #[derive(Serialize)]
+        struct Note {
+            #[serde(serialize_with = "unix_seconds::serialize")]
+            ctime: u64,
+        }
+        let value = to_value(&Note { ctime: u64::MAX }, span());
+        assert!(matches!(value, Value::Error { .. }), "{:?}", value);
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, PartialOrd, Eq, Ord)]
+    enum Shape {
+        Point,
+        Circle(i64),
+        Line(i64, i64),
+        Rect { w: i64, h: i64 },
+    }
+
+    #[test]
+    fn enums_are_externally_tagged() {
+        assert_eq!(to_value(&Shape::Point, span()), Value::string("Point", span()));
+        assert_eq!(
+            to_value(&Shape::Circle(2), span()),
+            Value::Record { cols: vec!["Circle".into()], vals: vec![Value::int(2, span())], span: span() }
+        );
+
+        let shapes = vec![Shape::Point, Shape::Circle(2), Shape::Line(1, 2), Shape::Rect { w: 3, h: 4 }];
+        assert_eq!(from_value::<Vec<Shape>>(&to_value(&shapes, span())).unwrap(), shapes);
+    }
+
+    #[test]
+    fn map_keys_become_columns() {
+        let by_id = BTreeMap::from([(1u32, "Opt"), (20, "Shock")]);
+        match to_value(&by_id, span()) {
+            Value::Record { cols, .. } => assert_eq!(cols, ["1", "20"]),
+            other => panic!("Expected a record, but got {:?}", other),
+        }
+
+        let by_shape = BTreeMap::from([(Shape::Point, 1i64)]);
+        let value = to_value(&by_shape, span());
+        assert_eq!(from_value::<BTreeMap<Shape, i64>>(&value).unwrap(), by_shape);
+
+        let by_list = BTreeMap::from([(vec![1i64], 1i64)]);
+        assert!(matches!(to_value(&by_list, span()), Value::Error { .. }));
+    }
+}
@@ -0,0 +1,217 @@
+//! Serving any number of `PluginSignatures` enums from one plugin binary.
+use std::collections::HashMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::time::Instant;
+
+use nu_plugin::{EvaluatedCall, LabeledError};
+use nu_protocol::{PluginSignature, Value};
+
+use crate::custom::{self, PluginValue};
+use crate::{cli, logging, panic, protocol, runtime, Commands, Dispatch, Encoding, Error, Middleware, Plugin, PluginSignatures, PipelineOutput};
+
+/// Builds up the commands of a plugin binary, then serves them.
+///
+/// Each `add_*` call brings in the commands of one `PluginSignatures` enum,
+/// so independent command families can share a binary without sharing an
+/// enum. Calls are routed to the enum that declared the command.
+///
+/// ```ignore
+/// PluginServer::new()
+///     .add_main(&mut mtg_main)
+///     .add_handler::<DeckPlugin, _>(&mut Decks::default())
+///     .serve()
+/// ```
+///
+/// [`serve_plugins!`](crate::serve_plugins) is shorthand for the common
+/// case of one closure per enum.
+pub struct PluginServer<'a> {
+    families: Vec<Box<dyn Commands + 'a>>,
+    middleware: Vec<Box<dyn Middleware + 'a>>,
+    encoding: Encoding,
+}
+
+impl<'a> Default for PluginServer<'a> {
+    fn default() -> Self {
+        PluginServer { families: vec![], middleware: vec![], encoding: Encoding::MsgPack }
+    }
+}
+
+impl<'a> PluginServer<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Speaks `encoding` to nushell, unless `NAP_ENCODING` says otherwise.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Runs `middleware` around every command, see [`Middleware`].
+    pub fn middleware(mut self, middleware: impl Middleware + 'a) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Lets the plugin expand `T` values handed back by nushell, see
+    /// [`custom`](crate::custom).
+    pub fn custom_value<T: PluginValue>(self) -> Self {
+        custom::register::<T>();
+        self
+    }
+
+    /// Adds the commands of `T`, run by `main`.
+    pub fn add_main<T, O, F>(self, mut main: F) -> Self
+    where
+        T: PluginSignatures + 'a,
+        O: Into<PipelineOutput> + 'a,
+        F: FnMut(T, &Value) -> Result<O, LabeledError> + 'a,
+    {
+        self.add_with_state(|| (), move |_: &mut (), data: T, _: &EvaluatedCall, input: &Value| main(data, input))
+    }
+
+    /// Adds the commands of `T`, run by `main` with state created by `init`.
+    /// See [`serve_plugin_with_state`](crate::serve_plugin_with_state).
+    pub fn add_with_state<T, S, O, I, F>(mut self, init: I, main: F) -> Self
+    where
+        T: PluginSignatures + 'a,
+        S: 'a,
+        O: Into<PipelineOutput> + 'a,
+        I: FnOnce() -> S,
+        F: FnMut(&mut S, T, &EvaluatedCall, &Value) -> Result<O, LabeledError> + 'a,
+    {
+        self.families.push(Box::new(Plugin { data: PhantomData::<T>, state: init(), main }));
+        self
+    }
+
+    /// Adds the commands of `T`, run by the matching methods of `handler`.
+    pub fn add_handler<T: Dispatch<H> + 'a, H>(self, handler: &'a mut H) -> Self {
+        self.add_with_state(|| (), move |_: &mut (), data: T, call: &EvaluatedCall, input: &Value| {
+            data.dispatch(handler, call, input)
+        })
+    }
+
+    /// Adds the commands of `T`, run by an `async fn`. See
+    /// [`serve_plugin_async`](crate::serve_plugin_async).
+    pub fn add_async<T, O, F, Fut>(self, mut main: F) -> Self
+    where
+        T: PluginSignatures + 'a,
+        O: Into<PipelineOutput> + 'a,
+        F: FnMut(T, Value) -> Fut + 'a,
+        Fut: Future<Output = Result<O, LabeledError>>,
+    {
+        self.add_main(move |data: T, input: &Value| {
+            runtime::block_on_with_deadline(async { main(data, input.clone()).await.map(Into::into) })
+        })
+    }
+
+    /// Checks that no command name is declared by more than one enum.
+    pub fn check(&self) -> Result<(), Error> {
+        let mut seen = HashMap::new();
+        let mut duplicates = vec![];
+        for (idx, family) in self.families.iter().enumerate() {
+            for sig in family.signature() {
+                if let Some(first) = seen.insert(sig.sig.name.clone(), idx) {
+                    if first != idx {
+                        duplicates.push(sig.sig.name);
+                    }
+                }
+            }
+        }
+        if duplicates.is_empty() {
+            Ok(())
+        } else {
+            duplicates.sort();
+            Err(Error::new("Commands are declared more than once")
+                .label(duplicates.iter().map(|name| format!("`{}`", name)).collect::<Vec<_>>().join(", "))
+                .help("every command name has to belong to exactly one PluginSignatures enum"))
+        }
+    }
+
+    /// Serves every added command to nushell. Exits with an error if two
+    /// enums declare the same command.
+    pub fn serve(mut self) {
+        if let Err(e) = self.check() {
+            cli::report("", &e.into());
+            std::process::exit(1)
+        }
+        panic::install_hook();
+        logging::init();
+        let encoding = Encoding::from_env().unwrap_or(self.encoding);
+        protocol::serve(&mut self, encoding)
+    }
+
+    fn run_with_middleware(&mut self, name: &str, call: &EvaluatedCall, input: &Value) -> Result<PipelineOutput, LabeledError> {
+        if self.middleware.is_empty() {
+            return self.route(name, call, input);
+        }
+
+        for middleware in self.middleware.iter_mut() {
+            middleware.before(name, call);
+        }
+        let start = Instant::now();
+        // Collect streams here, so that `after` sees the whole result and
+        // the time it took to produce.
+        let result = self
+            .route(name, call, input)
+            .and_then(|output| panic::catch(name, call.head, || Ok(output.into_value())));
+        let elapsed = start.elapsed();
+        for middleware in self.middleware.iter_mut().rev() {
+            middleware.after(name, &result, elapsed);
+        }
+        result.map(PipelineOutput::from)
+    }
+
+    fn route(&mut self, name: &str, call: &EvaluatedCall, input: &Value) -> Result<PipelineOutput, LabeledError> {
+        let family = self
+            .families
+            .iter_mut()
+            .find(|family| family.signature().iter().any(|sig| sig.sig.name == name));
+        match family {
+            Some(family) => family.run(name, call, input),
+            None => Err(LabeledError {
+                label: "Plugin call with wrong name signature".into(),
+                msg: "The signature used to call the plugin does not match any known signature.".into(),
+                span: Some(call.head),
+            }),
+        }
+    }
+}
+
+impl<'a> Commands for PluginServer<'a> {
+    fn signature(&self) -> Vec<PluginSignature> {
+        self.families.iter().flat_map(|family| family.signature()).collect()
+    }
+
+    fn run(&mut self, name: &str, call: &EvaluatedCall, input: &Value) -> Result<PipelineOutput, LabeledError> {
+        logging::set_command(Some(name));
+        log::debug!("running");
+        let start = Instant::now();
+        let result = self.run_with_middleware(name, call, input);
+        match result {
+            Ok(_) => log::debug!("finished in {:?}", start.elapsed()),
+            Err(ref e) => log::debug!("failed after {:?}: {}", start.elapsed(), e.label),
+        }
+        logging::set_command(None);
+        result
+    }
+}
+
+/// Serves several `PluginSignatures` enums from one binary, each with its
+/// own entry point:
+///
+/// ```ignore
+/// nap::serve_plugins!(MtgPlugin => mtg_main, DeckPlugin => deck_main);
+/// ```
+///
+/// Entry points take the same arguments as with
+/// [`serve_plugin`](crate::serve_plugin).
+#[macro_export]
+macro_rules! serve_plugins {
+    ($($plugin:ty => $main:expr),+ $(,)?) => {
+        $crate::PluginServer::new()
+            $(.add_main::<$plugin, _, _>($main))+
+            .serve()
+    };
+}
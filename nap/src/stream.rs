@@ -0,0 +1,108 @@
+//! Output that is produced a piece at a time, such as search results that
+//! arrive a page at a time.
+//!
+//! Handlers return a [`PipelineOutput`] rather than a plain `Value` when
+//! they have a list to stream. The 0.77 plugin protocol can only send a
+//! single `Value` back for each call, so for now nap buffers streams into a
+//! `Value::List` just before replying, after the handler has returned.
+//! Handlers written this way won't need to change once the protocol can
+//! carry list streams.
+use futures::{Stream, StreamExt};
+use nu_plugin::LabeledError;
+use nu_protocol::{ShellError, Span, Value};
+
+use crate::runtime;
+
+pub enum PipelineOutput {
+    Value(Value),
+    ListStream {
+        values: Box<dyn Iterator<Item = Value>>,
+        span: Span,
+    },
+}
+
+impl PipelineOutput {
+    /// A list whose items are pulled from `values` as they are sent.
+    pub fn list_stream<I>(values: I, span: Span) -> Self
+    where
+        I: IntoIterator<Item = Value>,
+        I::IntoIter: 'static,
+    {
+        PipelineOutput::ListStream {
+            values: Box::new(values.into_iter()),
+            span,
+        }
+    }
+
+    /// A list whose items are pulled from an async `stream` as they are sent,
+    /// using the shared [`runtime`].
+    ///
+    /// Items are pulled after the handler has returned, but still count
+    /// against the running command's timeout: once it passes, the list ends
+    /// with the same "timed out" error [`runtime::block_on_with_deadline`]
+    /// gives, instead of waiting on the stream.
+    pub fn from_stream<S: Stream<Item = Value> + 'static>(stream: S, span: Span) -> Self {
+        let mut stream = Box::pin(stream);
+        let mut done = false;
+        let values = std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let next = runtime::block_on_with_deadline(async { Ok::<_, LabeledError>(stream.next().await) });
+            next.unwrap_or_else(|e| {
+                done = true;
+                Some(Value::Error {
+                    error: Box::new(ShellError::from(LabeledError { span: e.span.or(Some(span)), ..e })),
+                })
+            })
+        });
+        Self::list_stream(values, span)
+    }
+
+    /// Collects the whole output into one value. This is the buffering
+    /// fallback used when the protocol can't stream.
+    pub fn into_value(self) -> Value {
+        match self {
+            PipelineOutput::Value(value) => value,
+            PipelineOutput::ListStream { values, span } => Value::List {
+                vals: values.collect(),
+                span,
+            },
+        }
+    }
+}
+
+impl From<Value> for PipelineOutput {
+    fn from(value: Value) -> Self {
+        PipelineOutput::Value(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::stream;
+
+    use super::*;
+
+    #[test]
+    fn slow_streams_end_with_a_timeout_error() {
+        let _lock = runtime::DEADLINE_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let span = Span::new(0, 10);
+        let first = Value::string("Opt", span);
+        let slow = stream::once(futures::future::ready(first.clone())).chain(stream::pending());
+
+        runtime::start_deadline("mtg search", Some(Duration::from_millis(50)));
+        let value = PipelineOutput::from_stream(slow, span).into_value();
+        runtime::start_deadline("mtg search", None);
+
+        let vals = value.as_list().unwrap();
+        assert_eq!(vals.len(), 2);
+        assert_eq!(vals[0], first);
+        match &vals[1] {
+            Value::Error { error } => assert!(format!("{:?}", error).contains("`mtg search` timed out")),
+            other => panic!("Expected a timeout error, but got {:?}", other),
+        }
+    }
+}
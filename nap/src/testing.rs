@@ -0,0 +1,334 @@
+//! Helpers for testing plugin commands without registering the plugin in a
+//! real nushell.
+//!
+//! ```ignore
+//! let value = nap::testing::run_handler::<MtgPlugin, _>(
+//!     &mut Mtg,
+//!     "mtg search Opt --set m21",
+//!     Value::nothing(Span::unknown()),
+//! ).unwrap();
+//! nap::testing::assert_table(&value, &["name", "uuid"]);
+//! ```
+//!
+//! Command lines are split on whitespace, with single, double and backtick
+//! quotes for arguments containing spaces. Each argument is converted to a
+//! value using the shape from the command's signature, and carries the span
+//! of its text in the command line, so errors can be checked against spans.
+//! Commands marked `#[cache]` always run their handler here: the cache is
+//! neither read nor written.
+//!
+//! [`ProcessDriver`] runs the same kind of checks against a compiled plugin
+//! binary instead, speaking the real protocol over its stdin and stdout.
+use std::ffi::OsString;
+use std::io::{BufReader, Read};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use nu_plugin::{EvaluatedCall, LabeledError};
+use nu_protocol::{PluginSignature, Span, Value};
+
+use crate::protocol::{CallInfo, CallInput, PluginCall, PluginResponse};
+use crate::{args, cache, Commands, Dispatch, Encoding, Error, Plugin, PluginSignatures, PipelineOutput};
+
+/// Builds the call nushell would send for `line`, using `signatures` to
+/// find the command and to tell flags, switches and positionals apart.
+/// Returns the name of the command along with the call.
+pub fn parse_command_line(signatures: &[PluginSignature], line: &str) -> Result<(String, EvaluatedCall), LabeledError> {
+    args::parse(signatures, args::tokenize(line)?, Span::new(0, line.len()))
+}
+
+/// Parses `line` into a call and then into `T`, the way the plugin would
+/// see it if nushell had run `line`.
+pub fn parse<T: PluginSignatures>(line: &str) -> Result<T, LabeledError> {
+    let (name, call) = parse_command_line(&T::signature(), line)?;
+    T::parse_call(&name, &call)
+}
+
+/// Runs `line` against `main`, exactly as serving the plugin would, and
+/// returns the output with any list stream collected.
+pub fn run<T, O, F>(main: &mut F, line: &str, input: Value) -> Result<Value, LabeledError>
+where
+    T: PluginSignatures,
+    O: Into<PipelineOutput>,
+    F: FnMut(T, &Value) -> Result<O, LabeledError>,
+{
+    let mut plugin = Plugin {
+        data: PhantomData::<T>,
+        state: (),
+        main: |_: &mut (), data: T, _: &EvaluatedCall, input: &Value| main(data, input),
+    };
+    let (name, call) = parse_command_line(&plugin.signature(), line)?;
+    cache::disabled(|| plugin.run(&name, &call, &input).map(PipelineOutput::into_value))
+}
+
+/// Runs `line` against the matching method of `handler`.
+pub fn run_handler<T: Dispatch<H>, H>(handler: &mut H, line: &str, input: Value) -> Result<Value, LabeledError> {
+    let mut plugin = Plugin {
+        data: PhantomData::<T>,
+        state: (),
+        main: |_: &mut (), data: T, call: &EvaluatedCall, input: &Value| data.dispatch(handler, call, input),
+    };
+    let (name, call) = parse_command_line(&plugin.signature(), line)?;
+    cache::disabled(|| plugin.run(&name, &call, &input).map(PipelineOutput::into_value))
+}
+
+/// Returns the value of column `col` of a record, panicking with the
+/// columns that are there if it's missing.
+#[track_caller]
+pub fn column<'v>(value: &'v Value, col: &str) -> &'v Value {
+    match value {
+        Value::Record { cols, vals, .. } => match cols.iter().position(|c| c == col) {
+            Some(idx) => &vals[idx],
+            None => panic!("Record has no column `{}`; its columns are {:?}", col, cols),
+        },
+        other => panic!("Expected a record, but got {:?}", other),
+    }
+}
+
+/// Asserts that `value` is a record with at least the columns `cols`.
+#[track_caller]
+pub fn assert_record(value: &Value, cols: &[&str]) {
+    for col in cols {
+        column(value, col);
+    }
+}
+
+/// Asserts that `value` is a record whose columns hold exactly `expected`,
+/// in order. Spans are ignored.
+#[track_caller]
+pub fn assert_record_eq(value: &Value, expected: &[(&str, Value)]) {
+    match value {
+        Value::Record { cols, vals, .. } => {
+            let actual = cols.iter().map(String::as_str).zip(vals.iter()).collect::<Vec<_>>();
+            let expected = expected.iter().map(|(col, val)| (*col, val)).collect::<Vec<_>>();
+            assert_eq!(actual, expected, "Record does not match");
+        }
+        other => panic!("Expected a record, but got {:?}", other),
+    }
+}
+
+/// Asserts that `value` is a table: a list in which every row is a record
+/// with at least the columns `cols`. Returns the rows.
+#[track_caller]
+pub fn assert_table<'v>(value: &'v Value, cols: &[&str]) -> &'v [Value] {
+    match value {
+        Value::List { vals, .. } => {
+            for (idx, row) in vals.iter().enumerate() {
+                match row {
+                    Value::Record { cols: row_cols, .. } => {
+                        for col in cols {
+                            assert!(
+                                row_cols.iter().any(|c| c == col),
+                                "Row {} has no column `{}`; its columns are {:?}", idx, col, row_cols
+                            );
+                        }
+                    }
+                    other => panic!("Row {} is not a record: {:?}", idx, other),
+                }
+            }
+            vals
+        }
+        other => panic!("Expected a table, but got {:?}", other),
+    }
+}
+
+/// Drives a compiled plugin binary over the plugin protocol, the way nushell
+/// would. Like nushell, every request starts a fresh plugin process.
+///
+/// In an integration test, cargo gives the path of the plugin binary:
+///
+/// ```ignore
+/// let driver = ProcessDriver::new(env!("CARGO_BIN_EXE_nu_plugin_mtg"))
+///     .encoding(Encoding::Json);
+/// let value = driver.run("mtg tutor Opt", Value::nothing(Span::unknown())).unwrap();
+/// ```
+pub struct ProcessDriver {
+    path: PathBuf,
+    encoding: Option<Encoding>,
+    envs: Vec<(OsString, OsString)>,
+}
+
+impl ProcessDriver {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ProcessDriver { path: path.into(), encoding: None, envs: vec![] }
+    }
+
+    /// Asks the plugin to speak `encoding`, through `NAP_ENCODING`. Without
+    /// this the plugin uses its own default. Either way, responses are
+    /// decoded with whatever the plugin announces in the handshake.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    /// Sets an environment variable for every plugin process started.
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Starts the plugin, sends it `call` and returns its response.
+    pub fn request(&self, call: &PluginCall) -> Result<PluginResponse, Error> {
+        let mut command = Command::new(&self.path);
+        command
+            .envs(self.envs.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(encoding) = self.encoding {
+            command.env("NAP_ENCODING", encoding.name());
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| Error::new(format!("Could not start {}", self.path.display())).source(e))?;
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+        let mut len = [0u8];
+        stdout.read_exact(&mut len)?;
+        let mut name = vec![0u8; len[0] as usize];
+        stdout.read_exact(&mut name)?;
+        let encoding = match name.as_slice() {
+            b"json" => Encoding::Json,
+            b"msgpack" => Encoding::MsgPack,
+            other => {
+                return Err(Error::new("Plugin announced an unknown encoding")
+                    .label(String::from_utf8_lossy(other)))
+            }
+        };
+
+        // Closing stdin after the call tells the plugin there's nothing more.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        encoding.encode(call, &mut stdin)?;
+        drop(stdin);
+
+        let response = encoding.decode::<PluginResponse>(&mut stdout);
+        let output = child.wait_with_output()?;
+        response.map_err(|e| match String::from_utf8_lossy(&output.stderr).trim() {
+            "" => e,
+            stderr => e.help(format!("the plugin wrote to stderr:\n{}", stderr)),
+        })
+    }
+
+    /// Asks the plugin for the signatures of its commands.
+    pub fn signature(&self) -> Result<Vec<PluginSignature>, Error> {
+        match self.request(&PluginCall::Signature)? {
+            PluginResponse::Signature(signatures) => Ok(signatures),
+            PluginResponse::Error(e) => Err(e.into()),
+            other => Err(Error::new("Expected signatures from the plugin").label(format!("got {}", other.summary()))),
+        }
+    }
+
+    /// Sends a call to the command `name`.
+    pub fn call(&self, name: &str, call: EvaluatedCall, input: Value) -> Result<PluginResponse, Error> {
+        self.request(&PluginCall::CallInfo(CallInfo {
+            name: name.to_string(),
+            call,
+            input: CallInput::Value(input),
+        }))
+    }
+
+    /// Parses `line` against the signatures the plugin reports, as
+    /// [`parse_command_line`] does, runs it, and returns the resulting
+    /// value. Custom values are returned as an error, since they can only
+    /// be read back by the plugin itself.
+    pub fn run(&self, line: &str, input: Value) -> Result<Value, LabeledError> {
+        let (name, call) = parse_command_line(&self.signature()?, line)?;
+        match self.call(&name, call, input)? {
+            PluginResponse::Value(value) => Ok(*value),
+            PluginResponse::Error(e) => Err(e),
+            other => Err(Error::new("Expected a value from the plugin").label(format!("got {}", other.summary())).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nu_protocol::SyntaxShape;
+
+    use super::*;
+
+    struct Greet {
+        name: String,
+    }
+
+    impl PluginSignatures for Greet {
+        fn signature() -> Vec<PluginSignature> {
+            vec![PluginSignature::build("greet").required("name", SyntaxShape::String, "who to greet")]
+        }
+
+        fn parse_call(_name: &str, call: &EvaluatedCall) -> Result<Self, LabeledError> {
+            Ok(Greet { name: call.req(0)? })
+        }
+    }
+
+    fn greet(data: Greet, _: &Value) -> Result<Value, LabeledError> {
+        let span = Span::unknown();
+        Ok(Value::Record {
+            cols: vec!["greeting".into()],
+            vals: vec![Value::string(format!("Hello, {}", data.name), span)],
+            span,
+        })
+    }
+
+    #[test]
+    fn run_parses_the_line_and_calls_main() {
+        let _lock = crate::runtime::DEADLINE_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let value = run(&mut greet, "greet 'Jace Beleren'", Value::nothing(Span::unknown())).unwrap();
+        assert_record_eq(&value, &[("greeting", Value::string("Hello, Jace Beleren", Span::unknown()))]);
+    }
+
+    #[test]
+    fn run_reports_parse_errors() {
+        let _lock = crate::runtime::DEADLINE_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let err = run(&mut greet, "greet", Value::nothing(Span::unknown())).unwrap_err();
+        assert_eq!(err.label, "Missing required positional argument");
+    }
+
+    struct Cached;
+
+    impl PluginSignatures for Cached {
+        fn signature() -> Vec<PluginSignature> {
+            vec![PluginSignature::build("cached")]
+        }
+
+        fn parse_call(_name: &str, _call: &EvaluatedCall) -> Result<Self, LabeledError> {
+            Ok(Cached)
+        }
+
+        fn cache_ttl(_name: &str) -> Option<std::time::Duration> {
+            Some(std::time::Duration::from_secs(3600))
+        }
+    }
+
+    #[test]
+    fn cached_commands_always_run() {
+        let _lock = crate::runtime::DEADLINE_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut runs = 0;
+        let mut count = |_: Cached, _: &Value| {
+            runs += 1;
+            Ok(Value::int(runs, Span::unknown()))
+        };
+        run(&mut count, "cached", Value::nothing(Span::unknown())).unwrap();
+        let value = run(&mut count, "cached", Value::nothing(Span::unknown())).unwrap();
+        assert_eq!(value, Value::int(2, Span::unknown()));
+    }
+
+    #[test]
+    fn tables_are_checked_row_by_row() {
+        let span = Span::unknown();
+        let row = |name: &str| Value::Record { cols: vec!["name".into()], vals: vec![Value::string(name, span)], span };
+        let table = Value::List { vals: vec![row("Opt"), row("Shock")], span };
+        assert_eq!(assert_table(&table, &["name"]).len(), 2);
+        assert_eq!(column(&table.as_list().unwrap()[1], "name"), &Value::string("Shock", span));
+    }
+
+    #[test]
+    #[should_panic(expected = "Row 0 has no column `uuid`")]
+    fn missing_columns_fail_the_table_check() {
+        let _lock = crate::panic::HOOK_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let span = Span::unknown();
+        let row = Value::Record { cols: vec!["name".into()], vals: vec![Value::string("Opt", span)], span };
+        assert_table(&Value::List { vals: vec![row], span }, &["name", "uuid"]);
+    }
+}
@@ -0,0 +1,164 @@
+//! Conversions from Rust types into nushell values, for output types that
+//! want more control over their columns than going through serde gives.
+//!
+//! Usually implemented with `#[derive(IntoValue)]` from `nap_derive`.
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Offset, TimeZone, Utc};
+use nu_protocol::{ShellError, Span, Value};
+
+pub trait IntoValue {
+    fn to_value(&self, span: Span) -> Value;
+}
+
+impl IntoValue for Value {
+    fn to_value(&self, _span: Span) -> Value {
+        self.clone()
+    }
+}
+
+impl IntoValue for String {
+    fn to_value(&self, span: Span) -> Value {
+        Value::string(self.clone(), span)
+    }
+}
+
+impl IntoValue for str {
+    fn to_value(&self, span: Span) -> Value {
+        Value::string(self, span)
+    }
+}
+
+impl IntoValue for bool {
+    fn to_value(&self, span: Span) -> Value {
+        Value::boolean(*self, span)
+    }
+}
+
+macro_rules! int_to_value {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoValue for $ty {
+                fn to_value(&self, span: Span) -> Value {
+                    // Nushell ints are i64, so anything bigger has to lose
+                    // precision.
+                    match i64::try_from(*self) {
+                        Ok(val) => Value::int(val, span),
+                        Err(_) => Value::float(*self as f64, span),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+int_to_value!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoValue for f32 {
+    fn to_value(&self, span: Span) -> Value {
+        Value::float((*self).into(), span)
+    }
+}
+
+impl IntoValue for f64 {
+    fn to_value(&self, span: Span) -> Value {
+        Value::float(*self, span)
+    }
+}
+
+impl<Tz: TimeZone> IntoValue for DateTime<Tz> {
+    fn to_value(&self, span: Span) -> Value {
+        Value::Date { val: self.with_timezone(&self.offset().fix()), span }
+    }
+}
+
+impl<T: IntoValue + ?Sized> IntoValue for &T {
+    fn to_value(&self, span: Span) -> Value {
+        (**self).to_value(span)
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn to_value(&self, span: Span) -> Value {
+        match self {
+            Some(val) => val.to_value(span),
+            None => Value::nothing(span),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn to_value(&self, span: Span) -> Value {
+        Value::List {
+            vals: self.iter().map(|v| v.to_value(span)).collect(),
+            span,
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for BTreeMap<String, T> {
+    fn to_value(&self, span: Span) -> Value {
+        Value::Record {
+            cols: self.keys().cloned().collect(),
+            vals: self.values().map(|v| v.to_value(span)).collect(),
+            span,
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn to_value(&self, span: Span) -> Value {
+        // Sort the columns so the output doesn't change from run to run.
+        let mut entries = self.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        Value::Record {
+            cols: entries.iter().map(|(k, _)| (*k).clone()).collect(),
+            vals: entries.iter().map(|(_, v)| v.to_value(span)).collect(),
+            span,
+        }
+    }
+}
+
+impl IntoValue for serde_json::Value {
+    fn to_value(&self, span: Span) -> Value {
+        crate::to_value(self, span)
+    }
+}
+
+fn out_of_range(what: &str, span: Span) -> Value {
+    Value::Error {
+        error: Box::new(ShellError::GenericError(
+            format!("{} out of range", what),
+            format!("This {} can't be represented in nushell", what),
+            Some(span),
+            None,
+            vec![],
+        )),
+    }
+}
+
+/// Renders a count of seconds since the Unix epoch as a date. Used by
+/// `#[value(date = "unix_seconds")]`.
+pub fn unix_seconds<T: Copy + TryInto<i64>>(secs: &T, span: Span) -> Value {
+    match (*secs).try_into().ok().and_then(|secs| Utc.timestamp_opt(secs, 0).single()) {
+        Some(date) => date.to_value(span),
+        None => out_of_range("timestamp", span),
+    }
+}
+
+/// Renders a count of milliseconds since the Unix epoch as a date. Used by
+/// `#[value(date = "unix_millis")]`.
+pub fn unix_millis<T: Copy + TryInto<i64>>(millis: &T, span: Span) -> Value {
+    match (*millis).try_into().ok().and_then(|millis| Utc.timestamp_millis_opt(millis).single()) {
+        Some(date) => date.to_value(span),
+        None => out_of_range("timestamp", span),
+    }
+}
+
+/// Renders a number of bytes as a filesize. Used by `#[value(filesize)]`.
+pub fn filesize<T: Copy + TryInto<i64>>(bytes: &T, span: Span) -> Value {
+    match (*bytes).try_into() {
+        Ok(val) => Value::Filesize { val, span },
+        Err(_) => out_of_range("filesize", span),
+    }
+}
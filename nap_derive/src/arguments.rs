@@ -7,7 +7,7 @@
 //! through token streams, and are also easy to convert out to Nushell
 //! signatures when we're done walking.
 use proc_macro2::TokenStream;
-use syn::{Type, PathArguments, GenericArgument, Field, parse_quote};
+use syn::{Type, PathArguments, GenericArgument, Field, parse_quote, ext::IdentExt};
 use quote::quote;
 
 pub enum ArgKind {
@@ -89,7 +89,8 @@ pub struct Arg {
 impl Arg {
     pub fn from_field(field: &Field) -> Self {
         Arg {
-            name: field.ident.as_ref().unwrap().to_string(),
+            // `r#type` is the `type` argument.
+            name: field.ident.as_ref().unwrap().unraw().to_string(),
             kind: ArgKind::from_field(field),
             usage: field.find_attr_str("usage")
         }
@@ -0,0 +1,139 @@
+//! `#[derive(IntoValue)]`, which turns a struct with named fields into a
+//! `Value::Record` with one column per field.
+//!
+//! Fields can be tweaked with `#[value(...)]`:
+//!
+//! - `rename = "col"` uses `col` as the column name.
+//! - `skip` leaves the field out entirely.
+//! - `flatten` splices the columns of a nested record into this one.
+//! - `date = "unix_seconds"` or `date = "unix_millis"` renders an integer
+//!   timestamp as a date.
+//! - `filesize` renders an integer number of bytes as a filesize.
+//! - `skip_if_none` leaves an `Option` field out when it is `None`, rather
+//!   than emitting nothing.
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, Field, LitStr, Error, Result, Expr, parse_quote};
+
+#[derive(Default)]
+struct ValueAttrs {
+    rename: Option<String>,
+    skip: bool,
+    flatten: bool,
+    date: Option<LitStr>,
+    filesize: bool,
+    skip_if_none: bool,
+}
+
+impl ValueAttrs {
+    fn from_field(field: &Field) -> Result<Self> {
+        let mut attrs = ValueAttrs::default();
+        for attr in &field.attrs {
+            if !attr.path().is_ident("value") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    attrs.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("skip") {
+                    attrs.skip = true;
+                } else if meta.path.is_ident("flatten") {
+                    attrs.flatten = true;
+                } else if meta.path.is_ident("date") {
+                    attrs.date = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("filesize") {
+                    attrs.filesize = true;
+                } else if meta.path.is_ident("skip_if_none") {
+                    attrs.skip_if_none = true;
+                } else {
+                    return Err(meta.error("unknown value attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(attrs)
+    }
+
+    /// The expression converting `field` (a reference) into a `Value`.
+    fn conversion(&self, field: &Expr) -> Result<Expr> {
+        if let Some(ref date) = self.date {
+            return match date.value().as_str() {
+                "unix_seconds" => Ok(parse_quote! { nap::value::unix_seconds(#field, span) }),
+                "unix_millis" => Ok(parse_quote! { nap::value::unix_millis(#field, span) }),
+                _ => Err(Error::new(
+                    date.span(),
+                    "expected date = \"unix_seconds\" or date = \"unix_millis\""
+                )),
+            };
+        }
+        if self.filesize {
+            return Ok(parse_quote! { nap::value::filesize(#field, span) });
+        }
+        Ok(parse_quote! { nap::IntoValue::to_value(#field, span) })
+    }
+}
+
+pub fn derive(input: DeriveInput) -> Result<TokenStream> {
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => return Err(Error::new_spanned(
+                &input.ident,
+                "IntoValue can only be derived for structs with named fields"
+            )),
+        },
+        _ => return Err(Error::new_spanned(
+            &input.ident,
+            "IntoValue can only be derived for structs"
+        )),
+    };
+
+    let mut columns = vec![];
+    for field in fields {
+        let attrs = ValueAttrs::from_field(field)?;
+        if attrs.skip {
+            continue;
+        }
+
+        let ident = field.ident.as_ref().unwrap();
+        let col = attrs.rename.clone().unwrap_or_else(|| ident.to_string());
+
+        if attrs.flatten {
+            columns.push(quote! {
+                if let nu_protocol::Value::Record { cols: inner_cols, vals: inner_vals, .. } =
+                    nap::IntoValue::to_value(&self.#ident, span)
+                {
+                    cols.extend(inner_cols);
+                    vals.extend(inner_vals);
+                }
+            });
+        } else if attrs.skip_if_none {
+            let conversion = attrs.conversion(&parse_quote! { inner })?;
+            columns.push(quote! {
+                if let Some(ref inner) = self.#ident {
+                    cols.push(#col.to_string());
+                    vals.push(#conversion);
+                }
+            });
+        } else {
+            let conversion = attrs.conversion(&parse_quote! { &self.#ident })?;
+            columns.push(quote! {
+                cols.push(#col.to_string());
+                vals.push(#conversion);
+            });
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics nap::IntoValue for #name #ty_generics #where_clause {
+            fn to_value(&self, span: nu_protocol::Span) -> nu_protocol::Value {
+                let mut cols: Vec<String> = vec![];
+                let mut vals: Vec<nu_protocol::Value> = vec![];
+                #(#columns)*
+                nu_protocol::Value::Record { cols, vals, span }
+            }
+        }
+    })
+}
@@ -1,12 +1,63 @@
 mod arguments;
+mod into_value;
 
 use std::{collections::HashMap};
 
 use arguments::{ArgKind, Arg};
-use quote::{quote};
-use syn::{parse_macro_input, DeriveInput, Expr, punctuated::Punctuated, Token, LitStr, parse_quote, Data, FieldValue, ExprStruct, Arm};
+use quote::{quote, format_ident};
+use syn::{parse_macro_input, DeriveInput, Expr, punctuated::Punctuated, Token, LitStr, parse_quote, Data, FieldValue, ExprStruct, Arm, TraitItemFn, FnArg, Ident};
 
-#[proc_macro_derive(PluginSignatures, attributes(signature, req, opt, flag, usage))]
+/// Everything we learn about a single variant while walking it, kept around
+/// until all variants have been seen and we can emit the impls.
+struct Command {
+    sig_builder: Expr,
+    parser: ExprStruct,
+    compiler_errors: Option<Expr>,
+    handler_method: TraitItemFn,
+    dispatch_arm: Arm,
+    /// Argument names and completer functions from `#[complete(...)]`.
+    completers: Vec<(String, syn::Path)>,
+    /// From `#[timeout(...)]`.
+    timeout_millis: Option<u64>,
+    /// From `#[cache(...)]`.
+    cache_millis: Option<u64>,
+}
+
+/// Parses durations like `500ms`, `30s`, `1.5m`, `2h` or `1d` into
+/// milliseconds.
+fn parse_millis(text: &str) -> Option<u64> {
+    let split = text.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let scale = match unit.trim() {
+        "ms" => 1.0,
+        "s" => 1000.0,
+        "m" => 60.0 * 1000.0,
+        "h" => 60.0 * 60.0 * 1000.0,
+        "d" => 24.0 * 60.0 * 60.0 * 1000.0,
+        _ => None?,
+    };
+    Some((number * scale).round() as u64)
+}
+
+/// Converts a variant name such as `NoteList` into the method name
+/// `note_list` used on the generated handler trait.
+fn snake_case(ident: &Ident) -> Ident {
+    let mut name = String::new();
+    for (idx, c) in ident.to_string().chars().enumerate() {
+        if c.is_uppercase() {
+            if idx > 0 {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+    Ident::new(&name, ident.span())
+}
+
+#[proc_macro_derive(PluginSignatures, attributes(signature, req, opt, flag, usage, stream, complete, timeout, cache))]
 pub fn derive_plugin_signatures(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let enum_data = if let Data::Enum(enum_data) = input.data {
@@ -20,7 +71,9 @@ pub fn derive_plugin_signatures(input: proc_macro::TokenStream) -> proc_macro::T
     };
     
     let name = input.ident;
-    let mut signature_data: HashMap<String, (Expr, ExprStruct, Option<Expr>)> =
+    let vis = input.vis;
+    let handler_name = format_ident!("{}Handler", name);
+    let mut signature_data: HashMap<String, Command> =
         enum_data
             .variants
             .into_iter()
@@ -28,7 +81,14 @@ pub fn derive_plugin_signatures(input: proc_macro::TokenStream) -> proc_macro::T
                 let mut call_name: Option<String> = None;
                 let variant_name = variant.ident.clone();
                 let mut call_parsers: Vec<FieldValue> = vec![];
+                let mut handler_args: Vec<FnArg> = vec![];
+                let mut field_idents: Vec<Ident> = vec![];
                 let mut usage = None;
+                let mut streams = false;
+                let mut completers = vec![];
+                let mut timeout_millis = None;
+                let mut cache_millis = None;
+                let mut compiler_errors = Vec::<Expr>::new();
 
                 // Process attributes on the entire variant.
                 // TODO: allow adding examples and help from attributes.
@@ -44,17 +104,45 @@ pub fn derive_plugin_signatures(input: proc_macro::TokenStream) -> proc_macro::T
                         let lit: LitStr = attr.parse_args().unwrap();
                         usage = Some(lit.value());
                     }
+
+                    // #[timeout("30s")] bounds how long the command's
+                    // futures may run, see nap::runtime::block_on_with_deadline.
+                    // #[cache("1h")] keeps its results on disk that long,
+                    // see nap::cache.
+                    for (ident, millis) in [("timeout", &mut timeout_millis), ("cache", &mut cache_millis)] {
+                        if !attr.path().is_ident(ident) {
+                            continue;
+                        }
+                        let parsed = attr.parse_args::<LitStr>().and_then(|lit| {
+                            parse_millis(&lit.value()).ok_or_else(|| syn::Error::new(
+                                lit.span(),
+                                "Expected a duration such as \"500ms\", \"30s\", \"2m\" or \"1h\"."
+                            ))
+                        });
+                        match parsed {
+                            Ok(parsed) => *millis = Some(parsed),
+                            Err(e) => {
+                                let e = e.to_compile_error();
+                                compiler_errors.push(parse_quote! { #e });
+                            }
+                        }
+                    }
+
+                    // #[stream] lets the handler answer with a
+                    // nap::PipelineOutput instead of a single Value.
+                    if attr.path().is_ident("stream") {
+                        streams = true;
+                    }
                 }
 
                 let call_name = call_name.unwrap();
                 let call_name_expr: Expr = parse_quote!(#call_name);
 
-                let mut compiler_errors = Vec::<Expr>::new();
                 let mut sig_builder: Expr = parse_quote! {
                     nu_protocol::PluginSignature::build(#call_name_expr)
                 };
                 
-                if let Some(usage) = usage {
+                if let Some(ref usage) = usage {
                     sig_builder = parse_quote! {
                         #sig_builder
                             .usage(#usage)
@@ -74,8 +162,22 @@ pub fn derive_plugin_signatures(input: proc_macro::TokenStream) -> proc_macro::T
                     // Add the required argument to both the signature and the call
                     // parser.
                     let field_ident = field.ident.unwrap();
+                    let field_ty = field.ty;
+                    handler_args.push(parse_quote! { #field_ident: #field_ty });
+                    field_idents.push(field_ident.clone());
                     let field_usage = arg.usage_quote();
                     let field_name = arg.name;
+                    for attr in &field.attrs {
+                        if attr.path().is_ident("complete") {
+                            match attr.parse_args::<syn::Path>() {
+                                Ok(path) => completers.push((field_name.clone(), path)),
+                                Err(e) => {
+                                    let e = e.to_compile_error();
+                                    compiler_errors.push(parse_quote! { #e });
+                                }
+                            }
+                        }
+                    }
                     match arg.kind {
                         ArgKind::Required(_) if seen_opt_yet => {
                             compiler_errors.push(parse_quote! {
@@ -101,8 +203,13 @@ pub fn derive_plugin_signatures(input: proc_macro::TokenStream) -> proc_macro::T
                                 #sig_builder
                                     .optional(#field_name, #shape, #field_usage)
                             };
+                            // The generated wrapper module passes missing
+                            // arguments on as null.
                             call_parsers.push(parse_quote! {
-                                #field_ident: call.opt(#req_idx)?
+                                #field_ident: match call.nth(#req_idx) {
+                                    None | Some(nu_protocol::Value::Nothing { .. }) => None,
+                                    Some(value) => Some(nu_protocol::FromValue::from_value(&value)?),
+                                }
                             });
                             req_idx += 1;
                         },
@@ -126,13 +233,24 @@ pub fn derive_plugin_signatures(input: proc_macro::TokenStream) -> proc_macro::T
                                     .named(#field_name, #shape, #field_usage, None)
                             };
                             call_parsers.push(parse_quote! {
-                                #field_ident: call.get_flag(#field_name)?
+                                #field_ident: match call.get_flag_value(#field_name) {
+                                    None | Some(nu_protocol::Value::Nothing { .. }) => None,
+                                    Some(value) => Some(nu_protocol::FromValue::from_value(&value)?),
+                                }
                             });
                         }
                         _ => todo!()
                     }
                 }
 
+                if cache_millis.is_some() {
+                    sig_builder = parse_quote! {
+                        #sig_builder
+                            .switch(nap::cache::NO_CACHE_FLAG, "Don't use cached results, nor cache this one.", None)
+                            .switch(nap::cache::REFRESH_FLAG, "Replace the cached result with a fresh one.", None)
+                    };
+                }
+
                 // TODO: fix spans
                 let call_parsers =
                     Punctuated::<FieldValue, Token![,]>::from_iter(call_parsers);
@@ -143,37 +261,102 @@ pub fn derive_plugin_signatures(input: proc_macro::TokenStream) -> proc_macro::T
                     None
                 };
 
+                let method_name = snake_case(&variant_name);
+                handler_args.push(parse_quote! { call: &nu_plugin::EvaluatedCall });
+                handler_args.push(parse_quote! { input: &nu_protocol::Value });
+                let handler_args = Punctuated::<FnArg, Token![,]>::from_iter(handler_args);
+                let handler_call_args = Punctuated::<Expr, Token![,]>::from_iter(
+                    field_idents
+                        .iter()
+                        .map(|ident| -> Expr { parse_quote! { #ident } })
+                        .chain([parse_quote! { call }, parse_quote! { input }])
+                );
+                let field_idents = Punctuated::<Ident, Token![,]>::from_iter(field_idents);
+                let method_doc = usage.unwrap_or_else(|| format!("Handles `{}`.", call_name));
+                let (output_ty, dispatch_expr): (syn::Type, Expr) = if streams {
+                    (
+                        parse_quote! { nap::PipelineOutput },
+                        parse_quote! { handler.#method_name(#handler_call_args) }
+                    )
+                } else {
+                    (
+                        parse_quote! { nu_protocol::Value },
+                        parse_quote! { handler.#method_name(#handler_call_args).map(nap::PipelineOutput::from) }
+                    )
+                };
+
                 (
                     call_name,
-                    (
+                    Command {
                         sig_builder,
-                        parse_quote! {
+                        parser: parse_quote! {
                             #name :: #variant_name {
                                 #call_parsers
                             }
                         },
-                        compiler_errors
-                    )
+                        compiler_errors,
+                        handler_method: parse_quote! {
+                            #[doc = #method_doc]
+                            // One argument per field, however many there are.
+                            #[allow(clippy::too_many_arguments)]
+                            fn #method_name(&mut self, #handler_args) -> Result<#output_ty, nu_plugin::LabeledError>;
+                        },
+                        dispatch_arm: parse_quote! {
+                            #name :: #variant_name { #field_idents } => #dispatch_expr
+                        },
+                        completers,
+                        timeout_millis,
+                        cache_millis,
+                    }
                 )
             })
         .collect();
 
     let mut signature_builders = vec![];
     let mut signature_parsers = vec![];
-    for s in signature_data.drain() {
-        signature_builders.push(s.1.0);
-        if let Some(errors) = s.1.2 {
+    let mut handler_methods = vec![];
+    let mut dispatch_arms = vec![];
+    let mut completer_checks = vec![];
+    let mut timeout_arms = vec![];
+    let mut cache_arms = vec![];
+    for (call_name, command) in signature_data.drain() {
+        if let Some(millis) = command.timeout_millis {
+            timeout_arms.push(quote! {
+                #call_name => Some(std::time::Duration::from_millis(#millis)),
+            });
+        }
+        if let Some(millis) = command.cache_millis {
+            cache_arms.push(quote! {
+                #call_name => Some(std::time::Duration::from_millis(#millis)),
+            });
+        }
+        signature_builders.push(command.sig_builder);
+        for (arg_name, path) in command.completers {
+            let usage = format!("Lists completions for `{}` of `{}`.", arg_name, call_name);
+            signature_builders.push(parse_quote! {
+                nu_protocol::PluginSignature::build(nap::completions::completer_name(#call_name, #arg_name))
+                    .usage(#usage)
+            });
+            completer_checks.push(quote! {
+                if name == nap::completions::completer_name(#call_name, #arg_name) {
+                    return Some(#path(call));
+                }
+            });
+        }
+        if let Some(errors) = command.compiler_errors {
             signature_builders.push(errors);
         }
-        let name = s.0;
-        let arm_expr = s.1.1;
+        let arm_expr = command.parser;
         let arm: Arm = parse_quote! {
-            #name => #arm_expr
+            #call_name => #arm_expr
         };
         signature_parsers.push(arm);
+        handler_methods.push(command.handler_method);
+        dispatch_arms.push(command.dispatch_arm);
     }
     let signature_builders = Punctuated::<Expr, Token![,]>::from_iter(signature_builders);
     let signature_parsers = Punctuated::<Arm, Token![,]>::from_iter(signature_parsers);
+    let dispatch_arms = Punctuated::<Arm, Token![,]>::from_iter(dispatch_arms);
 
     let parser_impl = quote! {
         fn parse_call(name: &str, call: &nu_plugin::EvaluatedCall) -> Result<Self, nu_plugin::LabeledError> {
@@ -189,7 +372,6 @@ pub fn derive_plugin_signatures(input: proc_macro::TokenStream) -> proc_macro::T
     };
 
     let expanded = quote! {
-        use nu_protocol;
 
         impl nap::PluginSignatures for #name {
             fn signature() -> Vec<nu_protocol::PluginSignature> {
@@ -199,8 +381,79 @@ pub fn derive_plugin_signatures(input: proc_macro::TokenStream) -> proc_macro::T
             }
 
             #parser_impl
+
+            fn complete(name: &str, call: &nu_plugin::EvaluatedCall) -> Option<Result<Vec<String>, nu_plugin::LabeledError>> {
+                #(#completer_checks)*
+                let _ = (name, call);
+                None
+            }
+
+            fn timeout(name: &str) -> Option<std::time::Duration> {
+                match name {
+                    #(#timeout_arms)*
+                    _ => None,
+                }
+            }
+
+            fn cache_ttl(name: &str) -> Option<std::time::Duration> {
+                match name {
+                    #(#cache_arms)*
+                    _ => None,
+                }
+            }
+        }
+
+        /// One method per command, generated by `#[derive(PluginSignatures)]`.
+        /// Implement this and pass it to `nap::serve_plugin_with_handler`.
+        #vis trait #handler_name {
+            #(#handler_methods)*
+        }
+
+        impl<H: #handler_name> nap::Dispatch<H> for #name {
+            fn dispatch(self, handler: &mut H, call: &nu_plugin::EvaluatedCall, input: &nu_protocol::Value) -> Result<nap::PipelineOutput, nu_plugin::LabeledError> {
+                match self {
+                    #dispatch_arms
+                }
+            }
         }
     };
 
     proc_macro::TokenStream::from(expanded)
 }
+
+#[proc_macro_derive(IntoValue, attributes(value))]
+pub fn derive_into_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    into_value::derive(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_take_a_unit() {
+        assert_eq!(parse_millis("250ms"), Some(250));
+        assert_eq!(parse_millis("30s"), Some(30_000));
+        assert_eq!(parse_millis("1.5s"), Some(1_500));
+        assert_eq!(parse_millis("5m"), Some(300_000));
+        assert_eq!(parse_millis("2h"), Some(7_200_000));
+        assert_eq!(parse_millis("1d"), Some(86_400_000));
+    }
+
+    #[test]
+    fn durations_without_a_known_unit_are_rejected() {
+        assert_eq!(parse_millis("30"), None);
+        assert_eq!(parse_millis("30w"), None);
+        assert_eq!(parse_millis("s"), None);
+        assert_eq!(parse_millis(""), None);
+    }
+
+    #[test]
+    fn variant_names_become_snake_case_methods() {
+        let ident = Ident::new("NoteList", proc_macro2::Span::call_site());
+        assert_eq!(snake_case(&ident).to_string(), "note_list");
+    }
+}
@@ -1,98 +1,287 @@
 mod values;
 use values::*;
 
-use nap::{serve_plugin};
+mod query;
+use query::Criteria;
+
+use futures::StreamExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use nap::{PluginServer, cache, runtime::{self, block_on_with_deadline}, PipelineOutput, Spanned};
 use nap_derive::PluginSignatures;
+use nu_plugin::{EvaluatedCall, LabeledError};
 use nu_protocol::{Value, Span};
-use scryfall::{Card, search::{query::{Query}, prelude as scry}};
-use tokio::runtime::Builder;
+use serde::Deserialize;
+use scryfall::{Card, Catalog, Set, list::ListIter, search::{query::{Query}, prelude as scry}};
 
+// Parsed once per call, so the size of `Search` doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(PluginSignatures)]
 pub enum MtgPlugin {
     #[signature("mtg tutor")]
     #[usage("Searches Scryfall for a single card and returns it.")]
+    #[cache("1d")]
     Tutor {
         #[req]
+        #[complete(card_names)]
         card_name: String,
 
         #[flag]
         #[usage("If set, will search for cards using a fuzzy match on the card name.")]
-        fuzzy: bool
+        fuzzy: bool,
+
+        #[flag]
+        #[usage("Return the card as an opaque handle, for `mtg prints`.")]
+        handle: bool
     },
 
     #[signature("mtg search")]
     #[usage("Searches Scryfall for cards matching a query and returns them.")]
+    #[stream]
     Search {
-        #[req]
-        #[usage("Name of the card to search for.")]
-        name: String,
+        #[opt]
+        #[usage("Name of the card to search for. May be left out when searching by the flags below.")]
+        #[complete(card_names)]
+        name: Option<String>,
 
         #[flag]
         #[usage("Only search this set or edition for cards.")]
-        set: Option<String>
+        #[complete(set_codes)]
+        set: Option<String>,
+
+        #[flag]
+        #[usage("Only cards of these colors, such as `wu`, `red` or `>=rg`.")]
+        color: Option<String>,
+
+        #[flag]
+        #[usage("Only cards whose color identity fits in these colors, such as `wub`. Prefix with `=` for an exact match.")]
+        identity: Option<String>,
+
+        #[flag]
+        #[usage("Only cards with this in their type line, such as `legendary creature`.")]
+        r#type: Option<String>,
+
+        #[flag]
+        #[usage("Only cards with this in their rules text.")]
+        oracle: Option<String>,
+
+        #[flag]
+        #[usage("Only cards with this mana value, such as `3` or `>=5`.")]
+        cmc: Option<String>,
+
+        #[flag]
+        #[usage("Only cards of this rarity, such as `mythic` or `>=rare`.")]
+        rarity: Option<String>,
+
+        #[flag]
+        #[usage("Only cards legal in this format, such as `modern`.")]
+        format: Option<String>,
+
+        #[flag]
+        #[usage("Same as --format.")]
+        legal: Option<String>,
+
+        #[flag]
+        #[usage("Only cards illustrated by this artist.")]
+        artist: Option<String>,
+
+        #[flag]
+        #[usage("Only creatures with this power, such as `3`, `>=4` or `>tou`.")]
+        power: Option<String>,
+
+        #[flag]
+        #[usage("Only creatures with this toughness, such as `1` or `<pow`.")]
+        toughness: Option<String>,
+
+        #[flag]
+        #[usage("Only cards printed in this year, such as `2020` or `<=1995`.")]
+        year: Option<String>,
+
+        #[flag]
+        #[usage("Only cards matching these comma-separated criteria, such as `commander,reprint`.")]
+        is: Option<String>
+    },
+
+    #[signature("mtg prints")]
+    #[usage("Lists every printing of the card handle from `mtg tutor --handle` piped in.")]
+    #[stream]
+    Prints {}
+}
+
+struct Mtg {
+    /// Kept as a result so that a broken config file is reported by the
+    /// first command run, instead of being silently replaced by defaults.
+    config: Result<MtgConfig, LabeledError>,
+}
+
+impl Mtg {
+    fn config(&self) -> Result<&MtgConfig, LabeledError> {
+        self.config.as_ref().map_err(Clone::clone)
     }
 }
 
-fn nu_main(call: MtgPlugin, _input: &Value) -> Result<Value, nu_plugin::LabeledError> {
-    let runtime = Builder::new_multi_thread()
-        .enable_io()
-        .build()
-        .unwrap();
-
-    let a = async {
-        match call {
-            MtgPlugin::Tutor { card_name, fuzzy } => {
-                match if fuzzy {
-                    Card::named_fuzzy(&card_name).await
-                } else {
-                    Card::named(&card_name).await
-                } {
-                    Ok(card) => {
-                        Ok(card_as_value(&card))
-                    },
-                    Err(e) => {
-                        Err(nu_plugin::LabeledError {
-                            label: e.to_string(),
-                            msg: "Card not found.".into(),
-                            span: None
-                        })
-                    }
-                }
-            },
-
-            MtgPlugin::Search { name, set } => {
-                let query = Query::And(if let Some(set) = set {
-                    vec![
-                        scry::name(name),
-                        scry::set(set),
-                    ]
-                } else {
-                    vec![
-                        scry::name(name)
-                    ]
-                });
-                match Card::search_all(query).await {
-                    Ok(cards) => {
-                        Ok(Value::List {
-                            vals: cards.iter().map(card_as_value).collect(),
-                            span: Span::unknown()
-                        })
-                    },
-                    Err(e) => {
-                        Err(nu_plugin::LabeledError {
-                            label: e.to_string(),
-                            msg: "Card not found.".into(),
-                            span: None
-                        })
-                    }
-                }
+/// Settings read from `~/.config/nu_plugin_mtg/config.toml`, or from
+/// `NU_PLUGIN_MTG_*` environment variables.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+struct MtgConfig {
+    /// How many seconds a command may spend waiting on Scryfall.
+    timeout_secs: u64,
+    /// Where to keep cards looked up recently.
+    cache_dir: Option<PathBuf>,
+}
+
+impl Default for MtgConfig {
+    fn default() -> Self {
+        MtgConfig { timeout_secs: 30, cache_dir: None }
+    }
+}
+
+impl MtgPluginHandler for Mtg {
+    fn tutor(&mut self, card_name: String, fuzzy: bool, handle: bool, call: &EvaluatedCall, _input: &Value) -> Result<Value, LabeledError> {
+        self.config()?;
+        block_on_with_deadline(async {
+            let card = if fuzzy {
+                Card::named_fuzzy(&card_name).await
+            } else {
+                Card::named(&card_name).await
             }
+                .map_err(|e| nap::Error::from(e)
+                    .label("Card not found.")
+                    .help("Try --fuzzy if you aren't sure of the exact card name.")
+                )
+                .at(call.head)?;
+            if handle {
+                nap::custom::into_value(&CardHandle(card), call.head)
+            } else {
+                Ok(card_as_value(&card))
+            }
+        })
+    }
+
+    fn search(&mut self, name: Option<String>, set: Option<String>, color: Option<String>, identity: Option<String>, r#type: Option<String>, oracle: Option<String>, cmc: Option<String>, rarity: Option<String>, format: Option<String>, legal: Option<String>, artist: Option<String>, power: Option<String>, toughness: Option<String>, year: Option<String>, is: Option<String>, call: &EvaluatedCall, _input: &Value) -> Result<PipelineOutput, LabeledError> {
+        self.config()?;
+        let criteria = Criteria {
+            color,
+            identity,
+            r#type,
+            oracle,
+            cmc,
+            rarity,
+            format,
+            legal,
+            artist,
+            power,
+            toughness,
+            year,
+            is
+        };
+        let mut queries = vec![];
+        if let Some(name) = name {
+            queries.push(scry::name(name));
         }
-    };
+        if let Some(set) = set {
+            queries.push(scry::set(set));
+        }
+        queries.extend(criteria.queries(call.head)?);
+        if queries.is_empty() {
+            return Err(nap::Error::new("Nothing to search for")
+                .label("give a card name or a search flag")
+                .help("for example `mtg search --type dragon --cmc <=4`")
+                .span(call.head)
+                .into());
+        }
+        let query = Query::And(queries);
+        let cards = block_on_with_deadline(async {
+            Card::search(query)
+                .await
+                .map_err(|e| nap::Error::from(e).label("No cards found."))
+                .at(call.head)
+        })?;
+        Ok(card_stream(cards, call.head))
+    }
+
+    fn prints(&mut self, call: &EvaluatedCall, input: &Value) -> Result<PipelineOutput, LabeledError> {
+        self.config()?;
+        let CardHandle(card) = nap::custom::from_value::<CardHandle>(input)?;
+        let query = Query::Custom(format!("!\"{}\" unique:prints", card.name));
+        let cards = block_on_with_deadline(async {
+            Card::search(query)
+                .await
+                .map_err(|e| nap::Error::from(e).label("No printings found."))
+                .at(call.head)
+        })?;
+        Ok(card_stream(cards, call.head))
+    }
+}
 
-    runtime.block_on(a)
+/// Streams search results as records. Only the first page has been fetched
+/// so far; the rest are fetched as the results are consumed.
+fn card_stream(cards: ListIter<Card>, head: Span) -> PipelineOutput {
+    PipelineOutput::from_stream(
+        cards.into_stream().map(move |card| match card {
+            Ok(card) => card_as_value(&card),
+            Err(e) => Value::Error {
+                error: Box::new(LabeledError::from(nap::Error::from(e).span(head)).into())
+            }
+        }),
+        Span::unknown()
+    )
+}
+
+/// How long completers keep what they fetched. New cards and sets only
+/// show up with a set release.
+const COMPLETIONS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Every English card name, for completing card name arguments.
+fn card_names(call: &EvaluatedCall) -> Result<Vec<String>, LabeledError> {
+    cache::remember("card names", COMPLETIONS_TTL, call.head, || {
+        let catalog = block_on_with_deadline(async {
+            Catalog::card_names()
+                .await
+                .map_err(|e| nap::Error::from(e).label("Could not fetch card names."))
+                .at(call.head)
+        })?;
+        Ok(catalog.data)
+    })
+}
+
+/// The codes of every set, for completing `--set`.
+fn set_codes(call: &EvaluatedCall) -> Result<Vec<String>, LabeledError> {
+    cache::remember("set codes", COMPLETIONS_TTL, call.head, || {
+        block_on_with_deadline(async {
+            let sets = Set::all()
+                .await
+                .map_err(|e| nap::Error::from(e).label("Could not fetch sets."))
+                .at(call.head)?;
+            let codes = sets
+                .into_stream()
+                .filter_map(|set| async move { set.ok().map(|set| set.code.get().to_string()) })
+                .collect()
+                .await;
+            Ok(codes)
+        })
+    })
 }
 
 fn main() {
-    serve_plugin(&mut nu_main)
+    let mut mtg = Mtg {
+        config: nap::config::load::<MtgConfig>("nu_plugin_mtg").map_err(LabeledError::from),
+    };
+    let defaults = MtgConfig::default();
+    let config = mtg.config.as_ref().unwrap_or(&defaults);
+    // Every command talks to Scryfall, which shouldn't take this long.
+    let _ = runtime::configure(runtime::RuntimeConfig {
+        default_timeout: Some(Duration::from_secs(config.timeout_secs)),
+        ..Default::default()
+    });
+    let _ = cache::configure(cache::CacheConfig {
+        dir: config.cache_dir.clone(),
+        ..Default::default()
+    });
+    PluginServer::new()
+        .custom_value::<CardHandle>()
+        .add_handler::<MtgPlugin, _>(&mut mtg)
+        .serve()
 }
@@ -0,0 +1,331 @@
+//! Turns the flags of `mtg search` into Scryfall search criteria, such as
+//! `--cmc ">=3"` into `cmc>=3`. See https://scryfall.com/docs/syntax.
+use nu_plugin::LabeledError;
+use nu_protocol::Span;
+use scryfall::card::{Color, Colors, Multicolored, Rarity};
+use scryfall::format::Format;
+use scryfall::search::param::compare::Compare;
+use scryfall::search::param::criteria::{CardIs, PrintingIs};
+use scryfall::search::param::value::{NumProperty, ParamValue};
+use scryfall::search::prelude as scry;
+use scryfall::search::query::Query;
+
+const OPERATORS: [&str; 7] = [">=", "<=", "!=", ">", "<", "=", ":"];
+
+fn invalid(flag: &str, value: &str, help: &str, span: Span) -> LabeledError {
+    nap::Error::new(format!("Invalid --{}", flag))
+        .label(format!("`{}` {}", value, help))
+        .span(span)
+        .into()
+}
+
+/// Splits a leading comparison like `>=` off `value`. `:` means the same as
+/// leaving it out, as it does on Scryfall.
+fn split_operator(value: &str) -> (Option<&'static str>, &str) {
+    for op in OPERATORS {
+        if let Some(rest) = value.strip_prefix(op) {
+            return ((op != ":").then_some(op), rest.trim());
+        }
+    }
+    (None, value.trim())
+}
+
+/// `value` compared with `op`, or with `default` if there's no operator.
+fn compare<T: ParamValue>(op: Option<&str>, default: &str, value: T) -> Compare<T> {
+    match op.unwrap_or(default) {
+        ">=" => scry::gte(value),
+        "<=" => scry::lte(value),
+        "!=" => scry::neq(value),
+        ">" => scry::gt(value),
+        "<" => scry::lt(value),
+        _ => scry::eq(value),
+    }
+}
+
+/// What a color flag compares against: some colors, colorless, or any two
+/// or more colors.
+enum ColorSet {
+    Colors(Colors),
+    Multicolored,
+}
+
+/// Color letters like `wu`, or one of `white`, `blue`, `black`, `red`,
+/// `green`, `colorless` and `multicolor`.
+fn color_set(value: &str) -> Option<ColorSet> {
+    let value = value.to_lowercase();
+    let named = match value.as_str() {
+        "white" => Some(Color::White),
+        "blue" => Some(Color::Blue),
+        "black" => Some(Color::Black),
+        "red" => Some(Color::Red),
+        "green" => Some(Color::Green),
+        "colorless" => Some(Color::Colorless),
+        "multicolor" | "multicolored" | "m" => return Some(ColorSet::Multicolored),
+        _ => None,
+    };
+    let colors = match named {
+        Some(color) => vec![color],
+        None => value
+            .chars()
+            .map(|letter| match letter {
+                'w' => Some(Color::White),
+                'u' => Some(Color::Blue),
+                'b' => Some(Color::Black),
+                'r' => Some(Color::Red),
+                'g' => Some(Color::Green),
+                'c' => Some(Color::Colorless),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?,
+    };
+    (!colors.is_empty()).then(|| ColorSet::Colors(Colors::from(colors.as_slice())))
+}
+
+/// A color flag as the query `param` makes of it.
+fn colors(
+    flag: &str,
+    value: &str,
+    default: &str,
+    param: fn(Compare<Colors>) -> Query,
+    multicolored: fn(Compare<Multicolored>) -> Query,
+    help: &str,
+    span: Span,
+) -> Result<Query, LabeledError> {
+    let (op, operand) = split_operator(value);
+    match color_set(operand) {
+        Some(ColorSet::Colors(colors)) => Ok(param(compare(op, default, colors))),
+        Some(ColorSet::Multicolored) => Ok(multicolored(compare(op, default, Multicolored))),
+        None => Err(invalid(flag, value, help, span)),
+    }
+}
+
+fn rarity(value: &str) -> Option<Rarity> {
+    Some(match value.to_lowercase().as_str() {
+        "c" | "common" => Rarity::Common,
+        "u" | "uncommon" => Rarity::Uncommon,
+        "r" | "rare" => Rarity::Rare,
+        "m" | "mythic" => Rarity::Mythic,
+        "s" | "special" => Rarity::Special,
+        "b" | "bonus" => Rarity::Bonus,
+        _ => return None,
+    })
+}
+
+fn format(value: &str) -> Option<Format> {
+    Some(match value.to_lowercase().as_str() {
+        "standard" => Format::Standard,
+        "future" => Format::Future,
+        "historic" => Format::Historic,
+        "gladiator" => Format::Gladiator,
+        "pioneer" => Format::Pioneer,
+        "explorer" => Format::Explorer,
+        "modern" => Format::Modern,
+        "legacy" => Format::Legacy,
+        "pauper" => Format::Pauper,
+        "vintage" => Format::Vintage,
+        "penny" => Format::Penny,
+        "commander" => Format::Commander,
+        "brawl" => Format::Brawl,
+        "historicbrawl" => Format::HistoricBrawl,
+        "alchemy" => Format::Alchemy,
+        "paupercommander" => Format::PauperCommander,
+        "duel" => Format::Duel,
+        "oldschool" => Format::OldSchool,
+        "premodern" => Format::Premodern,
+        _ => return None,
+    })
+}
+
+/// A power or toughness to compare against: a number, or another stat of
+/// the same card.
+enum Stat {
+    Number(f64),
+    Property(NumProperty),
+}
+
+fn stat(value: &str) -> Option<Stat> {
+    Some(match value.to_lowercase().as_str() {
+        "pow" | "power" => Stat::Property(NumProperty::Power),
+        "tou" | "toughness" => Stat::Property(NumProperty::Toughness),
+        "cmc" => Stat::Property(NumProperty::Cmc),
+        number => Stat::Number(number.parse().ok()?),
+    })
+}
+
+/// A power or toughness flag as the query `param` makes of it.
+fn compare_stat(
+    flag: &str,
+    value: &str,
+    param: fn(Compare<f64>) -> Query,
+    property: fn(Compare<NumProperty>) -> Query,
+    help: &str,
+    span: Span,
+) -> Result<Query, LabeledError> {
+    let (op, operand) = split_operator(value);
+    match stat(operand) {
+        Some(Stat::Number(number)) => Ok(param(compare(op, "=", number))),
+        Some(Stat::Property(other)) => Ok(property(compare(op, "=", other))),
+        None => Err(invalid(flag, value, help, span)),
+    }
+}
+
+/// One `is:` criterion, such as `commander` or `reprint`.
+fn criterion(value: &str) -> Option<Query> {
+    let card = match value.to_lowercase().as_str() {
+        "commander" => CardIs::Commander,
+        "companion" => CardIs::Companion,
+        "vanilla" => CardIs::Vanilla,
+        "frenchvanilla" => CardIs::FrenchVanilla,
+        "permanent" => CardIs::Permanent,
+        "spell" => CardIs::Spell,
+        "split" => CardIs::Split,
+        "transform" => CardIs::Transform,
+        "modal" | "mdfc" => CardIs::Modal,
+        "hybrid" => CardIs::Hybrid,
+        "phyrexian" => CardIs::Phyrexian,
+        "reserved" => CardIs::Reserved,
+        "funny" => CardIs::Funny,
+        "reprint" => return Some(PrintingIs::Reprint.into()),
+        "promo" => return Some(PrintingIs::Promo.into()),
+        "digital" => return Some(PrintingIs::Digital.into()),
+        // scryfall has no typed criterion for full art printings.
+        "full" | "fullart" => return Some(Query::Custom("is:full".into())),
+        _ => return None,
+    };
+    Some(card.into())
+}
+
+/// The `mtg search` flags besides the name and `--set`.
+#[derive(Default)]
+pub struct Criteria {
+    pub color: Option<String>,
+    pub identity: Option<String>,
+    pub r#type: Option<String>,
+    pub oracle: Option<String>,
+    pub cmc: Option<String>,
+    pub rarity: Option<String>,
+    pub format: Option<String>,
+    pub legal: Option<String>,
+    pub artist: Option<String>,
+    pub power: Option<String>,
+    pub toughness: Option<String>,
+    pub year: Option<String>,
+    pub is: Option<String>,
+}
+
+impl Criteria {
+    /// One query per flag that was given, in the order of the flags.
+    pub fn queries(&self, span: Span) -> Result<Vec<Query>, LabeledError> {
+        let mut queries = vec![];
+        if let Some(ref color) = self.color {
+            let help = "is not a color, such as `wu`, `>=red` or `multicolor`";
+            queries.push(colors("color", color, ">=", scry::color, scry::color, help, span)?);
+        }
+        if let Some(ref identity) = self.identity {
+            let help = "is not a color identity, such as `wubrg` or `=colorless`";
+            queries.push(colors("identity", identity, "<=", scry::color_identity, scry::color_identity, help, span)?);
+        }
+        if let Some(ref type_line) = self.r#type {
+            queries.push(scry::type_line(type_line.clone()));
+        }
+        if let Some(ref oracle) = self.oracle {
+            queries.push(scry::oracle_text(oracle.clone()));
+        }
+        if let Some(ref cmc) = self.cmc {
+            let (op, operand) = split_operator(cmc);
+            let cmc_value = operand
+                .parse::<f64>()
+                .map_err(|_| invalid("cmc", cmc, "is not a comparison, such as `3` or `>=3`", span))?;
+            queries.push(scry::cmc(compare(op, "=", cmc_value)));
+        }
+        if let Some(ref value) = self.rarity {
+            let (op, operand) = split_operator(value);
+            let rarity = rarity(operand)
+                .ok_or_else(|| invalid("rarity", value, "is not a rarity, such as `mythic` or `>=rare`", span))?;
+            queries.push(scry::rarity(compare(op, "=", rarity)));
+        }
+        for (flag, value) in [("format", &self.format), ("legal", &self.legal)] {
+            if let Some(value) = value {
+                let format = format(value)
+                    .ok_or_else(|| invalid(flag, value, "is not a format, such as `modern`", span))?;
+                queries.push(scry::format(format));
+            }
+        }
+        if let Some(ref artist) = self.artist {
+            queries.push(scry::artist(artist.clone()));
+        }
+        if let Some(ref power) = self.power {
+            let help = "is not a comparison, such as `3` or `>tou`";
+            queries.push(compare_stat("power", power, scry::power, scry::power, help, span)?);
+        }
+        if let Some(ref toughness) = self.toughness {
+            let help = "is not a comparison, such as `3` or `>pow`";
+            queries.push(compare_stat("toughness", toughness, scry::toughness, scry::toughness, help, span)?);
+        }
+        if let Some(ref year) = self.year {
+            let (op, operand) = split_operator(year);
+            let year_value = operand
+                .parse::<u32>()
+                .map_err(|_| invalid("year", year, "is not a year, such as `2020` or `<=1995`", span))?;
+            queries.push(scry::year(compare(op, "=", year_value)));
+        }
+        if let Some(ref is) = self.is {
+            for value in is.split(',').map(str::trim) {
+                let query = criterion(value)
+                    .ok_or_else(|| invalid("is", is, "is not a list of criteria, such as `commander,reprint`", span))?;
+                queries.push(query);
+            }
+        }
+        Ok(queries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_are_split_off() {
+        assert_eq!(split_operator(">= 3"), (Some(">="), "3"));
+        assert_eq!(split_operator(":red"), (None, "red"));
+        assert_eq!(split_operator("mythic"), (None, "mythic"));
+    }
+
+    #[test]
+    fn colors_are_letters_or_names() {
+        assert!(matches!(color_set("WU"), Some(ColorSet::Colors(_))));
+        assert!(matches!(color_set("colorless"), Some(ColorSet::Colors(_))));
+        assert!(matches!(color_set("multicolor"), Some(ColorSet::Multicolored)));
+        assert!(color_set("wx").is_none());
+        assert!(color_set("").is_none());
+    }
+
+    #[test]
+    fn every_given_flag_becomes_a_query() {
+        let criteria = Criteria {
+            color: Some(">=ub".into()),
+            r#type: Some("legendary creature".into()),
+            cmc: Some("<=4".into()),
+            power: Some(">tou".into()),
+            is: Some("commander, reprint".into()),
+            ..Default::default()
+        };
+        assert_eq!(criteria.queries(Span::unknown()).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn invalid_values_name_their_flag() {
+        let cases = [
+            (Criteria { cmc: Some(">=three".into()), ..Default::default() }, "Invalid --cmc"),
+            (Criteria { rarity: Some("legendary".into()), ..Default::default() }, "Invalid --rarity"),
+            (Criteria { legal: Some("tiny leaders".into()), ..Default::default() }, "Invalid --legal"),
+            (Criteria { toughness: Some("<loyalty".into()), ..Default::default() }, "Invalid --toughness"),
+            (Criteria { year: Some("'95".into()), ..Default::default() }, "Invalid --year"),
+            (Criteria { is: Some("commander,".into()), ..Default::default() }, "Invalid --is"),
+        ];
+        for (criteria, label) in cases {
+            let err = criteria.queries(Span::new(0, 10)).unwrap_err();
+            assert_eq!((err.label.as_str(), err.span), (label, Some(Span::new(0, 10))));
+        }
+    }
+}
@@ -1,5 +1,27 @@
+use nap::IntoValue;
+use nap::custom::PluginValue;
+use nu_plugin::LabeledError;
+use std::collections::HashMap;
+
+use chrono::{FixedOffset, NaiveDate, TimeZone};
 use nu_protocol::{Value, Span};
-use scryfall::Card;
+use scryfall::{Card, card::{CardFace, Color, Legality, Price}};
+use serde::{Deserialize, Serialize};
+
+/// A whole card, handed to nushell as is and only turned into a record when
+/// nushell needs one. `mtg tutor --handle` returns these, and `mtg prints`
+/// takes them.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(transparent)]
+pub struct CardHandle(pub Card);
+
+impl PluginValue for CardHandle {
+    const TYPE_NAME: &'static str = "mtg card";
+
+    fn to_base_value(&self, _span: Span) -> Result<Value, LabeledError> {
+        Ok(card_as_value(&self.0))
+    }
+}
 
 pub fn opt_as_value(value: Option<Value>) -> Value {
     match value {
@@ -8,33 +30,120 @@ pub fn opt_as_value(value: Option<Value>) -> Value {
     }
 }
 
-pub fn card_as_value(card: &Card) -> Value {
+fn colors_as_value(colors: &[Color]) -> Value {
+    Value::List {
+        vals: colors.iter().map(|c| Value::string(
+            format!("{:?}", c),
+            Span::unknown()
+        ))
+        .collect(),
+        span: Span::unknown()
+    }
+}
+
+fn date_as_value(date: NaiveDate) -> Value {
+    match (date.and_hms_opt(0, 0, 0), FixedOffset::east_opt(0)) {
+        (Some(date), Some(utc)) => Value::Date {
+            val: utc.from_utc_datetime(&date),
+            span: Span::unknown()
+        },
+        _ => Value::string(date.to_string(), Span::unknown())
+    }
+}
+
+fn legality_as_value(legality: &Legality) -> Value {
+    let name = match legality {
+        Legality::Legal => "legal",
+        Legality::NotLegal => "not_legal",
+        Legality::Restricted => "restricted",
+        Legality::Banned => "banned",
+    };
+    Value::string(name, Span::unknown())
+}
+
+/// A record with a column for each entry, sorted by name.
+fn map_as_value<K: ToString, V>(map: &HashMap<K, V>, as_value: impl Fn(&V) -> Value) -> Value {
+    let mut entries = map.iter().map(|(k, v)| (k.to_string(), as_value(v))).collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let (cols, vals) = entries.into_iter().unzip();
+    Value::Record { cols, vals, span: Span::unknown() }
+}
+
+fn image_uris_as_value<K: ToString, V: ToString>(uris: &HashMap<K, V>) -> Value {
+    map_as_value(uris, |uri| Value::string(uri.to_string(), Span::unknown()))
+}
+
+fn prices_as_value(prices: &Price) -> Value {
+    let span = Span::unknown();
     Value::Record {
-        cols: vec![
-            "arena_id".to_string(),
-            "uuid".to_string(),
-            "lang".to_string(),
-            "mtgo_id".to_string(),
-            // TODO: lots of fields
-            "name".to_string(),
-            // TODO: lots of fields
-            "color_identity".to_string(),
-        ],
+        cols: vec!["usd".into(), "usd_foil".into(), "eur".into(), "eur_foil".into(), "tix".into()],
         vals: vec![
-            opt_as_value(card.arena_id.map(|v| Value::string(v.to_string(), Span::unknown()))),
-            Value::string(card.id.to_string(), Span::unknown()),
-            Value::string(card.lang.to_string(), Span::unknown()),
-            opt_as_value(card.mtgo_id.map(|v| Value::string(v.to_string(), Span::unknown()))),
-            Value::string(card.name.to_string(), Span::unknown()),
-            Value::List {
-                vals: card.color_identity.iter().map(|c| Value::string(
-                    format!("{:?}", c),
-                    Span::unknown()
-                ))
-                .collect(),
-                span: Span::unknown()
-            }
+            prices.usd.to_value(span),
+            prices.usd_foil.to_value(span),
+            prices.eur.to_value(span),
+            prices.eur_foil.to_value(span),
+            prices.tix.to_value(span),
         ],
-        span: Span::unknown()
+        span
+    }
+}
+
+fn face_as_value(face: &CardFace) -> Value {
+    let span = Span::unknown();
+    let columns = vec![
+        ("name", face.name.to_value(span)),
+        ("mana_cost", face.mana_cost.to_value(span)),
+        ("type_line", face.type_line.to_value(span)),
+        ("oracle_text", face.oracle_text.to_value(span)),
+        ("power", face.power.to_value(span)),
+        ("toughness", face.toughness.to_value(span)),
+        ("colors", opt_as_value(face.colors.as_deref().map(colors_as_value))),
+        ("image_uris", opt_as_value(face.image_uris.as_ref().map(image_uris_as_value))),
+    ];
+    let (cols, vals) = columns.into_iter().map(|(col, val)| (col.to_string(), val)).unzip();
+    Value::Record { cols, vals, span }
+}
+
+pub fn card_as_value(card: &Card) -> Value {
+    let span = Span::unknown();
+    let columns = vec![
+        ("arena_id", opt_as_value(card.arena_id.map(|v| Value::string(v.to_string(), Span::unknown())))),
+        ("uuid", Value::string(card.id.to_string(), Span::unknown())),
+        ("lang", Value::string(card.lang.to_string(), Span::unknown())),
+        ("mtgo_id", opt_as_value(card.mtgo_id.map(|v| Value::string(v.to_string(), Span::unknown())))),
+        ("name", Value::string(card.name.to_string(), Span::unknown())),
+        ("mana_cost", card.mana_cost.to_value(span)),
+        ("cmc", card.cmc.to_value(span)),
+        ("type_line", card.type_line.to_value(span)),
+        ("oracle_text", card.oracle_text.to_value(span)),
+        ("power", card.power.to_value(span)),
+        ("toughness", card.toughness.to_value(span)),
+        ("loyalty", card.loyalty.to_value(span)),
+        ("colors", opt_as_value(card.colors.as_deref().map(colors_as_value))),
+        ("color_identity", colors_as_value(&card.color_identity)),
+        ("keywords", card.keywords.to_value(span)),
+        ("set", Value::string(card.set.get(), span)),
+        ("set_name", card.set_name.to_value(span)),
+        ("collector_number", card.collector_number.to_value(span)),
+        ("rarity", Value::string(format!("{:?}", card.rarity).to_lowercase(), span)),
+        ("artist", card.artist.to_value(span)),
+        ("released_at", date_as_value(card.released_at)),
+        ("prices", prices_as_value(&card.prices)),
+        ("legalities", map_as_value(&card.legalities, legality_as_value)),
+        ("image_uris", image_uris_as_value(&card.image_uris)),
+        ("card_faces", opt_as_value(card.card_faces.as_ref().map(|faces| Value::List {
+            vals: faces.iter().map(face_as_value).collect(),
+            span
+        }))),
+        ("scryfall_uri", Value::string(card.scryfall_uri.to_string(), span)),
+        // `Uri` has no `Display`, but serializes as the URL it wraps.
+        ("uri", nap::to_value(&card.uri, span)),
+    ];
+
+    let (cols, vals) = columns.into_iter().map(|(col, val)| (col.to_string(), val)).unzip();
+    Value::Record {
+        cols,
+        vals,
+        span
     }
 }
@@ -1,20 +1,13 @@
 use std::{collections::HashMap, borrow::Borrow};
 
 use keyring::Entry;
+use nap_derive::IntoValue;
 use nu_plugin::LabeledError;
 use serde::{Serialize, Deserialize, de::DeserializeOwned};
 use surf;
 
-use once_cell::sync::Lazy;
-
 use crate::errors::{SynError, Result};
 
-pub static CLIENT: Lazy<surf::Client> = Lazy::new(||
-    surf::Config::default()
-        .try_into()
-        .unwrap()
-);
-
 pub trait IntoParameters<'a> {
     type Output: Borrow<HashMap<&'a str, &'a str>>;
     fn into_parameters(self) -> Self::Output;
@@ -82,6 +75,7 @@ pub struct Server {
     name: String,
     endpoints: HashMap<String, ApiEndpoint>,
     session_id: Option<String>,
+    client: surf::Client,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -90,52 +84,80 @@ pub struct User {
     pub uid: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, IntoValue)]
 pub struct NotesList {
     pub notes: Vec<Note>,
     pub offset: usize,
     pub total: usize
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, IntoValue)]
 #[non_exhaustive]
 pub struct Note {
-    pub acl: serde_json::Value,
-    pub archive: bool,
+    // Fields are in the order their columns should appear in.
+    pub title: String,
+    // Missing when we run list instead of get.
+    #[value(skip_if_none)]
+    pub content: Option<String>,
     pub brief: String,
+    #[value(rename = "id")]
+    pub object_id: String,
+
+    #[value(skip)]
     pub category: String,
+    #[value(skip)]
+    pub tag: Option<Vec<String>>,
+    #[value(skip)]
+    pub owner: User,
+    #[value(skip)]
     pub ctime: u64,
-    pub encrypt: bool,
+    #[value(skip)]
     pub mtime: u64,
-    pub object_id: String,
-    pub owner: User,
+    #[value(skip)]
+    pub archive: bool,
+    #[value(skip)]
+    pub encrypt: bool,
+    #[value(skip)]
+    pub recycle: bool,
+    #[value(skip)]
+    pub acl: serde_json::Value,
+    #[value(skip)]
     pub parent_id: String,
+    #[value(skip)]
     pub perm: String,
-    pub recycle: bool,
+    #[value(skip)]
     pub thumb: serde_json::Value,
-    pub title: String,
+    #[value(skip)]
     pub ver: String,
 
     // These fields may be missing when we run list instead of get.
-    pub content: Option<String>,
+    #[value(skip)]
     pub attachment: Option<serde_json::Value>,
+    #[value(skip)]
     pub commit_msg: Option<serde_json::Value>,
+    #[value(skip)]
     pub individual_joined: Option<bool>,
+    #[value(skip)]
     pub individual_shared: Option<bool>,
+    #[value(skip)]
     pub latitude: Option<f64>,
+    #[value(skip)]
     pub longitude: Option<f64>,
+    #[value(skip)]
     pub link_id: Option<String>,
+    #[value(skip)]
     pub location: Option<String>,
+    #[value(skip)]
     pub source_url: Option<String>,
-    pub tag: Option<Vec<String>>,
 }
 
 impl Server {
-    pub async fn new<T: AsRef<str>>(name: T) -> Result<Self> {
+    pub async fn new<T: AsRef<str>>(name: T, client: surf::Client) -> Result<Self> {
         let mut new = Self {
             name: name.as_ref().to_string(),
             endpoints: HashMap::new(),
-            session_id: None
+            session_id: None,
+            client
         };
 
         new.endpoints = new.get_endpoints().await?;
@@ -143,10 +165,16 @@ impl Server {
         Ok(new)
     }
 
-    pub async fn from_keyring() -> Option<Self> {
-        let name = Entry::new("nu_syn", "server_name")
+    /// The name of the server `syn login` last logged in to, without
+    /// talking to it.
+    pub fn logged_in_name() -> Option<String> {
+        Entry::new("nu_syn", "server_name")
             .and_then(|e| e.get_password())
-            .ok()?;
+            .ok()
+    }
+
+    pub async fn from_keyring(client: surf::Client) -> Option<Self> {
+        let name = Self::logged_in_name()?;
         let session_id = Some(Entry::new("nu_syn", "session_id")
             .and_then(|e| e.get_password())
             .ok()?);
@@ -155,7 +183,8 @@ impl Server {
         let mut new = Self {
             name,
             endpoints: HashMap::new(),
-            session_id
+            session_id,
+            client
         };
 
         new.endpoints = new.get_endpoints().await.ok()?;
@@ -164,7 +193,7 @@ impl Server {
     }
 
     async fn send_get<T: DeserializeOwned>(&self, call: &ApiCall<'_>) -> Result<T> {
-        match (*CLIENT).send(surf::get(call.uri(self))).await {
+        match self.client.send(surf::get(call.uri(self))).await {
             Ok(mut resp) => {
                 resp
                     .body_bytes()
@@ -226,17 +255,15 @@ pub trait ServerOption: Sized {
     fn require(self) -> NuResult<Self::Output>;
 }
 
-impl ServerOption for Option<Server> {
-    type Output = Server;
+impl<S: Borrow<Server>> ServerOption for Option<S> {
+    type Output = S;
 
-    fn require(self) -> NuResult<Server> {
+    fn require(self) -> NuResult<S> {
         match self {
             None => {
-                Err(nu_plugin::LabeledError {
-                    label: "Not logged in".to_string(),
-                    msg: "Please run `dsn login <server-name>` first.".to_string(),
-                    span: None
-                })
+                Err(nap::Error::new("Not logged in")
+                    .help("Please run `syn login <server-name>` first.")
+                    .into())
             },
             Some(server) => Ok(server)
         }
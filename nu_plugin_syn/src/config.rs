@@ -0,0 +1,52 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Settings read from `~/.config/nu_plugin_syn/config.toml`, or from
+/// `NU_PLUGIN_SYN_*` environment variables.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SynConfig {
+    /// The NAS `syn login` logs in to when it isn't given one.
+    pub server: Option<String>,
+    /// How many seconds to wait for the NAS to answer a request.
+    pub timeout_secs: Option<u64>,
+    /// Where to keep NoteStation info fetched recently.
+    pub cache_dir: Option<PathBuf>,
+    pub tls: TlsConfig,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// A PEM file of extra certificates to trust, for a NAS using a
+    /// self-signed certificate.
+    pub ca_file: Option<PathBuf>,
+}
+
+impl SynConfig {
+    pub fn load() -> Result<Self, nap::Error> {
+        nap::config::load("nu_plugin_syn")
+    }
+
+    pub fn client(&self) -> Result<surf::Client, nap::Error> {
+        let mut config = surf::Config::new()
+            .set_timeout(self.timeout_secs.map(Duration::from_secs));
+        if let Some(ref ca_file) = self.tls.ca_file {
+            let mut tls = rustls::ClientConfig::new();
+            tls.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+            let file = File::open(ca_file)
+                .map_err(|e| nap::Error::new(format!("Could not open {}", ca_file.display())).source(e))?;
+            tls.root_store
+                .add_pem_file(&mut BufReader::new(file))
+                .map_err(|()| nap::Error::new(format!("{} is not a PEM certificate file", ca_file.display())))?;
+            config = config.set_tls_config(Some(Arc::new(tls)));
+        }
+        surf::Client::try_from(config)
+            .map_err(|e| nap::Error::new("Could not set up the HTTP client").label(e.to_string()))
+    }
+}
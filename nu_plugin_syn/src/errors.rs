@@ -27,3 +27,9 @@ impl SynError {
         }
     }
 }
+
+impl From<SynError> for nap::Error {
+    fn from(error: SynError) -> Self {
+        nap::Error::new("Synology API error").source(error)
+    }
+}
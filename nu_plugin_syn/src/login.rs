@@ -19,34 +19,19 @@ pub struct LoginCredentials {
 pub fn ask_for_credentials() -> Result<LoginCredentials, LabeledError> {    
     let mut username_input = Input::<String>::with_theme(&*THEME);
     username_input.with_prompt("Username");
-    let username = username_input.interact_text().or_else(|e| {
-        Err(nu_plugin::LabeledError {
-            label: e.kind().to_string(),
-            msg: format!("I/O error getting username: {}", e.to_string()),
-            span: None
-        })
-    })?;
+    let username = username_input.interact_text()
+        .map_err(|e| nap::Error::from(e).label("Could not read username"))?;
 
     let mut password_input = Password::with_theme(&*THEME);
     password_input.with_prompt("Password");
-    let password = password_input.interact().or_else(|e| {
-        Err(nu_plugin::LabeledError {
-            label: e.kind().to_string(),
-            msg: format!("I/O error getting username: {}", e.to_string()),
-            span: None
-        })
-    })?;
+    let password = password_input.interact()
+        .map_err(|e| nap::Error::from(e).label("Could not read password"))?;
 
     let mut otp_input = Input::<String>::with_theme(&*THEME);
     otp_input.allow_empty(true);
     otp_input.with_prompt("One-time code");
-    let otp = otp_input.interact_text().or_else(|e| {
-        Err(nu_plugin::LabeledError {
-            label: e.kind().to_string(),
-            msg: format!("I/O error getting username: {}", e.to_string()),
-            span: None
-        })
-    })?;
+    let otp = otp_input.interact_text()
+        .map_err(|e| nap::Error::from(e).label("Could not read one-time code"))?;
     let otp = if otp.trim().is_empty() {
         None
     } else {
@@ -57,16 +42,12 @@ pub fn ask_for_credentials() -> Result<LoginCredentials, LabeledError> {
 }
 
 
-pub async fn login<T: AsRef<str>>(server_name: T) -> Result<Value, LabeledError> {
+pub async fn login<T: AsRef<str>>(server_name: T, client: surf::Client) -> Result<Value, LabeledError> {
     let server_name = server_name.as_ref();
     let server = Server
-        ::new(server_name)
+        ::new(server_name, client)
         .await
-        .map_err(|e| LabeledError {
-            label: format!("Could not query API endpoints for {}", server_name),
-            msg: format!("{:?}", e),
-            span: None
-        })?;
+        .map_err(|e| nap::Error::new(format!("Could not query API endpoints for {}", server_name)).source(e))?;
     let credentials = ask_for_credentials()?;
 
     let mut parameters = HashMap::new();
@@ -83,27 +64,15 @@ pub async fn login<T: AsRef<str>>(server_name: T) -> Result<Value, LabeledError>
     let body = server
         .call::<Session>("SYNO.API.Auth", &parameters, Some(3))
         .await
-        .map_err(|e| LabeledError {
-            label: format!("Could not log in to {}", server_name),
-            msg: format!("{:?}", e),
-            span: None
-        })?;
+        .map_err(|e| nap::Error::new(format!("Could not log in to {}", server_name)).source(e))?;
 
     // If we made it this far, save the secrets out to the keyring.
     Entry::new("nu_syn", "server_name")
         .and_then(|e| e.set_password(server_name))
-        .map_err(|e| LabeledError {
-            label: format!("Could not save server name to keyring."),
-            msg: format!("{:?}", e),
-            span: None
-        })?;
+        .map_err(|e| nap::Error::new("Could not save server name to keyring.").source(e))?;
     Entry::new("nu_syn", "session_id")
         .and_then(|e| e.set_password(&body.sid))
-        .map_err(|e| LabeledError {
-            label: format!("Could not save session to keyring."),
-            msg: format!("{:?}", e),
-            span: None
-        })?;
+        .map_err(|e| nap::Error::new("Could not save session to keyring.").source(e))?;
 
 
     Ok(Value::String {
@@ -5,26 +5,24 @@ mod login;
 use login::*;
 
 mod api;
-use api::{Note, NotesList, Server, ServerOption};
-
-mod values;
-use values::IntoValue;
+use api::{Note, NotesList, NuResult, Server, ServerOption};
 
 mod errors;
 
-use nap::serve_plugin;
+mod config;
+use config::SynConfig;
+
+use nap::{cache, serve_plugin_with_state, Dispatch, IntoValue, runtime::block_on_with_deadline, Spanned};
 use nap_derive::PluginSignatures;
-use nu_plugin::LabeledError;
-use nu_protocol::{Span, Value};
+use nu_plugin::{EvaluatedCall, LabeledError};
+use nu_protocol::Value;
+
+use std::time::Duration;
 
 use dialoguer::theme::ColorfulTheme;
 
 use once_cell::sync::Lazy;
 
-use tokio::runtime::Builder;
-
-use pretty_env_logger;
-
 static THEME: Lazy<ColorfulTheme> = Lazy::new(|| ColorfulTheme::default());
 
 #[derive(PluginSignatures)]
@@ -32,121 +30,198 @@ pub enum SynPlugin {
     #[signature("syn login")]
     #[usage("Logs in to a given Synology NAS.")]
     Login {
-        #[req]
-        #[usage("The domain name of the Synology NAS to log in to.")]
-        name: String,
+        #[opt]
+        #[usage("The domain name of the Synology NAS to log in to. Defaults to `server` from the config file.")]
+        name: Option<String>,
     },
 
     #[signature("syn note info")]
+    #[timeout("30s")]
     Info {},
 
     #[signature("syn note list")]
+    #[timeout("30s")]
     NoteList {},
 
     #[signature("syn note get")]
+    #[timeout("30s")]
     NoteGet {
         #[opt]
+        #[complete(note_ids)]
         id: Option<String>,
     },
 }
 
-fn nu_main(call: SynPlugin, input: &Value) -> Result<Value, nu_plugin::LabeledError> {
-    console_hacks::reset_stdin();
-    pretty_env_logger::init();
-
-    let runtime = Builder::new_multi_thread().enable_io().build().unwrap();
-
-    let future = async {
-        match call {
-            SynPlugin::Login { name } => Ok(login(name).await?),
-
-            SynPlugin::Info {} => {
-                let server = Server::from_keyring().await.require()?;
-
-                let resp = server
-                    .call::<serde_json::Value>(
-                        "SYNO.NoteStation.Info",
-                        [
-                            ("method", "get")
-                        ],
-                        None,
-                    )
-                    .await
-                    .map_err(|e| LabeledError {
-                        label: "Error getting note info".to_string(),
-                        msg: format!("Error getting note info: {:?}", e),
-                        span: None,
-                    })?;
-
-                Ok(Value::String {
-                    val: serde_json::to_string(&resp).unwrap(),
-                    span: Span::unknown(),
-                })
-            }
+/// State for the call being served. Each call runs in a fresh process, so
+/// nothing here carries over to the next one; the session itself lives in
+/// the keyring.
+struct Syn {
+    /// Kept as a result so that a broken config file is reported by the
+    /// command that needed it, instead of by plugin registration.
+    config: Result<SynConfig, LabeledError>,
+    client: Option<surf::Client>,
+    /// The server we're logged in to, loaded from the keyring on first use.
+    server: Option<Server>,
+}
 
-            SynPlugin::NoteList {} => {
-                let server = Server::from_keyring().await.require()?;
-                let resp = server
-                    .call::<NotesList>(
-                        "SYNO.NoteStation.Note",
-                        [
-                            ("method", "list")
-                        ],
-                        Some(2),
-                    )
-                    .await
-                    .map_err(|e| LabeledError {
-                        label: "Error getting notes".to_string(),
-                        msg: format!("Error getting notes: {:?}", e),
-                        span: None,
-                    })?;
-
-                Ok(resp.notes.into_value())
-            }
+impl Syn {
+    fn new() -> Self {
+        let config = SynConfig::load().map_err(LabeledError::from);
+        if let Ok(ref config) = config {
+            let _ = cache::configure(cache::CacheConfig {
+                dir: config.cache_dir.clone(),
+                ..Default::default()
+            });
+        }
+        Syn {
+            config,
+            client: None,
+            server: None
+        }
+    }
 
-            SynPlugin::NoteGet { id } => {
-                let server = Server::from_keyring().await.require()?;
-
-                let ids = match id {
-                    Some(id) => vec![id],
-                    None => input
-                        .as_list()?
-                        .iter()
-                        .map(|i| i.as_string())
-                        .collect::<Result<Vec<_>, _>>()?,
-                };
-
-                {
-                    let server = &server;
-                    futures::future::join_all(ids.iter().map(|id| async move {
-                        server
-                            .call::<Note>(
-                                "SYNO.NoteStation.Note",
-                                [
-                                    ("method", "get"), ("object_id", id)
-                                ],
-                                Some(2),
-                            )
-                            .await
-                            .map_err(|e| LabeledError {
-                                label: format!("Error getting note {}", id),
-                                msg: format!("Error getting notes: {:?}", e),
-                                span: None,
-                            })
-                    }))
-                    .await
-                    .iter()
-                    .cloned()
-                    .collect::<Result<Vec<_>, _>>()
-                    .map(|v| v.into_value())
-                }
-            }
+    fn config(&self) -> NuResult<&SynConfig> {
+        self.config.as_ref().map_err(Clone::clone)
+    }
+
+    fn client(&mut self) -> NuResult<surf::Client> {
+        if self.client.is_none() {
+            self.client = Some(self.config()?.client()?);
+        }
+        Ok(self.client.clone().expect("just set"))
+    }
+
+    async fn server(&mut self) -> NuResult<&Server> {
+        if self.server.is_none() {
+            self.server = Server::from_keyring(self.client()?).await;
         }
-    };
+        self.server.as_ref().require()
+    }
+
+    fn fetch_info(&mut self, call: &EvaluatedCall) -> NuResult<serde_json::Value> {
+        block_on_with_deadline(async {
+            let server = self.server().await?;
+            server
+                .call::<serde_json::Value>(
+                    "SYNO.NoteStation.Info",
+                    [
+                        ("method", "get")
+                    ],
+                    None,
+                )
+                .await
+                .at(call.head)
+                .map_err(|e| e.label("Error getting note info").into())
+        })
+    }
+
+    async fn notes(&mut self, call: &EvaluatedCall) -> NuResult<NotesList> {
+        let server = self.server().await?;
+        let resp = server
+            .call::<NotesList>(
+                "SYNO.NoteStation.Note",
+                [
+                    ("method", "list")
+                ],
+                Some(2),
+            )
+            .await
+            .at(call.head)
+            .map_err(|e| e.label("Error getting notes"))?;
+        Ok(resp)
+    }
+}
+
+/// How long `syn note info` keeps what the NAS answered. It only changes
+/// when NoteStation is updated.
+const INFO_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The ids of every note, for completing `syn note get`.
+fn note_ids(call: &EvaluatedCall) -> NuResult<Vec<String>> {
+    let mut syn = Syn::new();
+    block_on_with_deadline(async {
+        let notes = syn.notes(call).await?;
+        Ok(notes.notes.into_iter().map(|note| note.object_id).collect())
+    })
+}
 
-    runtime.block_on(future)
+impl SynPluginHandler for Syn {
+    fn login(&mut self, name: Option<String>, call: &EvaluatedCall, _input: &Value) -> Result<Value, LabeledError> {
+        let name = match name.or_else(|| self.config().ok()?.server.clone()) {
+            Some(name) => name,
+            None => {
+                self.config()?;
+                return Err(nap::Error::new("No server to log in to")
+                    .label("give the NAS's domain name")
+                    .help("or set `server` in the config file")
+                    .span(call.head)
+                    .into());
+            }
+        };
+        let client = self.client()?;
+        block_on_with_deadline(async {
+            let result = login(name, client).await?;
+            // Pick the new session up from the keyring on the next call.
+            self.server = None;
+            Ok(result)
+        })
+    }
+
+    fn info(&mut self, call: &EvaluatedCall, _input: &Value) -> Result<Value, LabeledError> {
+        // Only NoteStation's version and settings, nothing from the notes
+        // themselves, so it can be kept on disk. Keyed by server, since
+        // `syn login` can switch to another NAS.
+        let info = match Server::logged_in_name() {
+            Some(name) => cache::remember(&format!("note info from {}", name), INFO_TTL, call.head, || self.fetch_info(call)),
+            None => self.fetch_info(call),
+        }?;
+        Ok(nap::to_value(&info, call.head))
+    }
+
+    fn note_list(&mut self, call: &EvaluatedCall, _input: &Value) -> Result<Value, LabeledError> {
+        block_on_with_deadline(async {
+            let resp = self.notes(call).await?;
+            Ok(resp.notes.to_value(call.head))
+        })
+    }
+
+    fn note_get(&mut self, id: Option<String>, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        block_on_with_deadline(async {
+            let server = self.server().await?;
+
+            let ids = match id {
+                Some(id) => vec![id],
+                None => nap::from_value::<Vec<String>>(input)?,
+            };
+
+            {
+                let server = &server;
+                futures::future::join_all(ids.iter().map(|id| async move {
+                    server
+                        .call::<Note>(
+                            "SYNO.NoteStation.Note",
+                            [
+                                ("method", "get"), ("object_id", id)
+                            ],
+                            Some(2),
+                        )
+                        .await
+                        .at(call.head)
+                        .map_err(|e| e.label(format!("Error getting note {}", id)))
+                }))
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .map(|v| v.to_value(call.head))
+                .map_err(LabeledError::from)
+            }
+        })
+    }
 }
 
 fn main() {
-    serve_plugin(&mut nu_main)
+    serve_plugin_with_state(Syn::new, &mut |syn: &mut Syn, data: SynPlugin, call: &EvaluatedCall, input: &Value| {
+        console_hacks::reset_stdin();
+        data.dispatch(syn, call, input)
+    })
 }